@@ -17,6 +17,12 @@ echo "Installing rudo binary to /usr/local/bin/..."
 cp target/release/rudo /usr/local/bin/rudo
 chown -R root:wheel /usr/local/bin/rudo
 chmod 4511 /usr/local/bin/rudo
+ln -sf rudo /usr/local/bin/rudoedit
+
+echo "Installing rudo-replay binary to /usr/local/bin/..."
+cp target/release/rudo-replay /usr/local/bin/rudo-replay
+chown -R root:wheel /usr/local/bin/rudo-replay
+chmod 0755 /usr/local/bin/rudo-replay
 
 echo "Generating PAM configuration..."
 unamestr=$(uname)
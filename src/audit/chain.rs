@@ -0,0 +1,464 @@
+//!
+//! Hash chain that makes changes to the audit log file evident
+//!
+//! Every line of the log has a sequence number and the SHA-256 hash of the line before it.
+//! After every AUDIT_CHECKPOINT_INTERVAL lines, a checkpoint line signs the chain so far with
+//! a key only root can read. The number and hash of the last line are kept in a signed head
+//! file next to the log, so removing lines from the end of the log is evident too.
+//!
+
+use std::io;
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::error::Error;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::os::unix::fs::OpenOptionsExt;
+
+use hex;
+use time;
+use serde::Serialize;
+use serde_json;
+use serde_json::Value;
+use hmac::{Hmac, Mac};
+use sha2::{Sha256, Digest};
+
+use keyfile;
+
+use AUDIT_KEY_PATH;
+use AUDIT_CHECKPOINT_INTERVAL;
+
+// Previous hash of the first line of a log
+static GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// A line of the log, with the fields of the event or checkpoint it holds
+#[derive(Serialize)]
+struct ChainedLine<'a, T: Serialize> {
+    seq: u64,           // Number of the line, starting at 0
+    prev_hash: &'a str, // Hex SHA-256 of the previous line
+    #[serde(flatten)]
+    event: &'a T,
+}
+
+/// Signature of the chain up to the checkpoint line
+#[derive(Serialize, Deserialize)]
+struct Checkpoint {
+    timestamp: String,
+    mac: String, // Hex HMAC-SHA256 of the line's seq, prev_hash and timestamp
+}
+
+#[derive(Serialize)]
+struct CheckpointEvent {
+    checkpoint: Checkpoint,
+}
+
+/// Last line written to a log, kept in `<log>.head`
+#[derive(Serialize, Deserialize)]
+struct Head {
+    seq: u64,
+    hash: String,
+    mac: String, // Hex HMAC-SHA256 of the log's path, seq and hash
+}
+
+/// Result of verifying a log
+pub struct Verification {
+    pub records: u64,
+    pub checkpoints: u64,
+    pub problems: Vec<String>, // Evidence of changes to the log
+    pub warnings: Vec<String>, // Parts of the log that couldn't be verified
+}
+
+fn line_hash(line: &str) -> String {
+    hex::encode(Sha256::digest(line.as_bytes()))
+}
+
+/// HMAC-SHA256 of the given fields, each terminated with a NUL so their boundaries can't be shifted
+fn fields_mac(key: &[u8], fields: &[&str]) -> Result<Hmac<Sha256>, Box<dyn Error>> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).map_err(|_| "Invalid audit key length!")?;
+    for field in fields.iter() {
+        mac.update(field.as_bytes());
+        mac.update(b"\0");
+    }
+    Ok(mac)
+}
+
+fn checkpoint_mac(key: &[u8], seq: u64, prev_hash: &str, timestamp: &str) -> Result<Hmac<Sha256>, Box<dyn Error>> {
+    fields_mac(key, &["checkpoint", &seq.to_string(), prev_hash, timestamp])
+}
+
+fn head_mac(key: &[u8], path: &str, seq: u64, hash: &str) -> Result<Hmac<Sha256>, Box<dyn Error>> {
+    fields_mac(key, &["head", path, &seq.to_string(), hash])
+}
+
+fn verify_mac(mac: Result<Hmac<Sha256>, Box<dyn Error>>, expected: &str) -> bool {
+    match (mac, hex::decode(expected)) {
+        (Ok(mac), Ok(tag)) => mac.verify_slice(&tag).is_ok(),
+        _ => false,
+    }
+}
+
+fn head_path(path: &str) -> String {
+    format!("{}.head", path)
+}
+
+/// Read the head of the log at path, if one was written
+fn read_head(path: &str) -> Result<Option<Head>, Box<dyn Error>> {
+    let mut buf = String::new();
+    match OpenOptions::new().read(true).custom_flags(libc::O_NOFOLLOW).open(head_path(path)) {
+        Ok(mut f) => f.read_to_string(&mut buf)?,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(Box::new(e)),
+    };
+    Ok(Some(serde_json::from_str(&buf)?))
+}
+
+/// Replace the head of the log at path
+fn write_head(path: &str, key: &[u8], seq: u64, hash: &str) -> Result<(), Box<dyn Error>> {
+    let head = Head {
+        seq,
+        hash: hash.to_string(),
+        mac: hex::encode(head_mac(key, path, seq, hash)?.finalize().into_bytes()),
+    };
+
+    let tmp_path = format!("{}.tmp", head_path(path));
+    let _ = fs::remove_file(&tmp_path);
+    let mut f = OpenOptions::new().write(true).create_new(true).mode(0o600)
+        .custom_flags(libc::O_NOFOLLOW).open(&tmp_path)?;
+    f.write_all(serde_json::to_string(&head)?.as_bytes())?;
+    f.sync_all()?;
+    fs::rename(&tmp_path, head_path(path))?;
+    Ok(())
+}
+
+/// Append an event to the log at path, which is open in f. The caller has to hold a lock
+/// on the log, and the head has to be untouched, since the new line continues from it.
+pub fn append_event<T: Serialize>(f: &mut File, path: &str, event: &T) -> Result<(), Box<dyn Error>> {
+    // A new chain is only started along with the key. Once there's a key, a log without
+    // a head was deleted or replaced, and continuing would cover that up.
+    let new_key = !Path::new(AUDIT_KEY_PATH).exists();
+    let key = keyfile::get_or_create_key(AUDIT_KEY_PATH)?;
+    append_chained(f, path, &key, new_key, event)
+}
+
+/// Append an event to the chain signed with key. Only starts a new chain if may_start is set.
+fn append_chained<T: Serialize>(f: &mut File, path: &str, key: &[u8], may_start: bool, event: &T)
+    -> Result<(), Box<dyn Error>> {
+    let (mut seq, mut prev_hash) = match read_head(path)? {
+        Some(head) => {
+            if !verify_mac(head_mac(key, path, head.seq, &head.hash), &head.mac) {
+                return Err(From::from(format!("{} has an invalid signature, check the log with --verify-log!",
+                                              head_path(path))));
+            }
+            (head.seq + 1, head.hash)
+        },
+        None if may_start => (0, String::from(GENESIS_HASH)),
+        None => return Err(From::from(format!("{} is missing, so the log was deleted or replaced. Check it with \
+                                               --verify-log, and remove {} to start a new log.",
+                                              head_path(path), AUDIT_KEY_PATH))),
+    };
+
+    let line = serde_json::to_string(&ChainedLine { seq, prev_hash: &prev_hash, event })?;
+    prev_hash = line_hash(&line);
+    let mut lines = format!("{}\n", line);
+
+    // Sign the chain so far once the interval is over
+    if (seq + 2) % AUDIT_CHECKPOINT_INTERVAL == 0 {
+        seq += 1;
+        let timestamp = time::OffsetDateTime::now_utc().format("%FT%TZ");
+        let mac = hex::encode(checkpoint_mac(key, seq, &prev_hash, &timestamp)?.finalize().into_bytes());
+        let checkpoint = CheckpointEvent { checkpoint: Checkpoint { timestamp, mac } };
+        let line = serde_json::to_string(&ChainedLine { seq, prev_hash: &prev_hash, event: &checkpoint })?;
+        prev_hash = line_hash(&line);
+        lines += &line;
+        lines.push('\n');
+    }
+
+    // Write everything at once, so the lines of concurrent invocations never interleave
+    f.write_all(lines.as_bytes())?;
+    f.sync_data()?;
+    write_head(path, key, seq, &prev_hash)
+}
+
+/// Verify the chain through the given logs, oldest first. If the last one is the current
+/// log at `current_path`, it's also checked against its head.
+pub fn verify(paths: &[String], current_path: &str) -> Result<Verification, Box<dyn Error>> {
+    verify_chain(paths, current_path, &keyfile::read_key(AUDIT_KEY_PATH)?)
+}
+
+/// Verify the chain through the given logs, signed with key
+fn verify_chain(paths: &[String], current_path: &str, key: &[u8]) -> Result<Verification, Box<dyn Error>> {
+    let mut res = Verification { records: 0, checkpoints: 0, problems: Vec::new(), warnings: Vec::new() };
+    let mut expected: Option<(u64, String)> = None; // Number and previous hash of the next line
+    let mut last_checkpoint: Option<u64> = None;
+
+    for path in paths.iter() {
+        let contents = match fs::read_to_string(path) {
+            Ok(v) => v,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                res.problems.push(format!("{}: the log is missing", path));
+                expected = None;
+                continue;
+            },
+            Err(e) => return Err(Box::new(e)),
+        };
+        if !contents.is_empty() && !contents.ends_with('\n') {
+            res.problems.push(format!("{}: the last line is incomplete, the log was cut off", path));
+        }
+
+        for (i, line) in contents.lines().enumerate() {
+            let location = format!("{}:{}", path, i + 1);
+            let value: Value = match serde_json::from_str(line) {
+                Ok(v) => v,
+                Err(_) => {
+                    res.problems.push(format!("{}: not a valid record", location));
+                    expected = None;
+                    continue;
+                },
+            };
+            let (seq, prev_hash) = match (value["seq"].as_u64(), value["prev_hash"].as_str()) {
+                (Some(seq), Some(prev_hash)) => (seq, prev_hash),
+                _ => {
+                    res.problems.push(format!("{}: record isn't part of the chain", location));
+                    expected = None;
+                    continue;
+                },
+            };
+
+            match expected {
+                None if seq == 0 && prev_hash != GENESIS_HASH => {
+                    res.problems.push(format!("{}: first record doesn't start a chain", location));
+                },
+                None if seq > 0 => {
+                    res.problems.push(format!("{}: log starts at record {}, the records before it are missing. \
+                                               If the log was rotated, give the older logs too.", location, seq));
+                },
+                Some((next, _)) if seq == next + 1 => {
+                    res.problems.push(format!("{}: record {} is missing", location, next));
+                },
+                Some((next, _)) if seq > next => {
+                    res.problems.push(format!("{}: records {} to {} are missing", location, next, seq - 1));
+                },
+                Some((next, _)) if seq < next => {
+                    res.problems.push(format!("{}: record {} appears again, the log was restarted or records \
+                                               were inserted", location, seq));
+                },
+                Some((_, ref hash)) if hash != prev_hash => {
+                    res.problems.push(format!("{}: record {} doesn't follow the record before it, one of them \
+                                               was modified", location, seq));
+                },
+                _ => {},
+            }
+
+            match value.get("checkpoint") {
+                Some(checkpoint) => {
+                    let valid = serde_json::from_value::<Checkpoint>(checkpoint.clone()).ok().is_some_and(|c| {
+                        verify_mac(checkpoint_mac(key, seq, prev_hash, &c.timestamp), &c.mac)
+                    });
+                    if valid {
+                        res.checkpoints += 1;
+                        last_checkpoint = Some(seq);
+                    } else {
+                        res.problems.push(format!("{}: checkpoint at record {} has an invalid signature",
+                                                  location, seq));
+                    }
+                },
+                None => res.records += 1,
+            }
+            expected = Some((seq + 1, line_hash(line)));
+        }
+    }
+
+    // The head shows whether lines were removed from or added to the end of the current log
+    let is_current = match (paths.last(), Path::new(current_path).canonicalize()) {
+        (Some(last), Ok(current)) => Path::new(last).canonicalize().is_ok_and(|p| p == current),
+        _ => false,
+    };
+    if !is_current {
+        let covered = last_checkpoint.map_or(String::from("at all"), |c| format!("after record {}", c));
+        res.warnings.push(format!("The last log isn't the current log {}, so records can't be checked {}",
+                                  current_path, covered));
+        return Ok(res);
+    }
+
+    let (last_seq, last_hash) = match expected {
+        Some((next, hash)) => (Some(next - 1), hash),
+        None => (None, String::new()),
+    };
+    match read_head(current_path)? {
+        None if last_seq.is_some() => {
+            res.problems.push(format!("{} is missing, so the end of the log can't be checked", head_path(current_path)));
+        },
+        // The head is written along with the first record, so the log was deleted or replaced
+        None => {
+            res.problems.push(format!("{} is missing, the log was deleted or replaced", head_path(current_path)));
+        },
+        Some(head) => {
+            if !verify_mac(head_mac(key, current_path, head.seq, &head.hash), &head.mac) {
+                res.problems.push(format!("{} has an invalid signature", head_path(current_path)));
+            } else if last_seq.is_none_or(|s| s < head.seq) {
+                res.problems.push(format!("The log ends before record {}, the last one that was written. \
+                                           It was truncated or deleted.", head.seq));
+            } else if last_seq.is_some_and(|s| s > head.seq) {
+                res.problems.push(format!("Records after {} weren't written by rudo", head.seq));
+            } else if last_hash != head.hash {
+                res.problems.push(format!("Record {} was modified", head.seq));
+            }
+        },
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::slice;
+    use std::process;
+    use std::path::PathBuf;
+
+    static KEY: [u8; 32] = [0x3c; 32];
+
+    /// A log in a directory of its own, which is removed when dropped
+    struct TestLog {
+        dir: PathBuf,
+        path: String,
+    }
+
+    impl TestLog {
+        fn new(test: &str) -> TestLog {
+            let dir = env::temp_dir().join(format!("rudo-chain-{}-{}", process::id(), test));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            let path = dir.join("rudo.log").to_string_lossy().into_owned();
+            TestLog { dir, path }
+        }
+    }
+
+    impl Drop for TestLog {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    /// Append events numbered from..to to the log
+    fn append(path: &str, from: u64, to: u64) {
+        let mut f = OpenOptions::new().append(true).create(true).open(path).unwrap();
+        for n in from..to {
+            append_chained(&mut f, path, &KEY, true, &serde_json::json!({ "n": n })).unwrap();
+        }
+    }
+
+    fn read_lines(path: &str) -> Vec<String> {
+        fs::read_to_string(path).unwrap().lines().map(String::from).collect()
+    }
+
+    fn write_lines(path: &str, lines: &[String]) {
+        fs::write(path, lines.iter().map(|l| format!("{}\n", l)).collect::<String>()).unwrap();
+    }
+
+    fn problems(path: &str) -> Vec<String> {
+        let res = verify_chain(&[path.to_string()], path, &KEY).unwrap();
+        assert!(res.warnings.is_empty(), "{:?}", res.warnings);
+        res.problems
+    }
+
+    fn assert_reported(problems: &[String], expected: &str) {
+        assert!(problems.iter().any(|p| p.contains(expected)), "{:?} doesn't mention {:?}", problems, expected);
+    }
+
+    #[test]
+    fn intact_log_verifies() {
+        let log = TestLog::new("intact");
+        let path = &log.path;
+        append(path, 0, AUDIT_CHECKPOINT_INTERVAL + 5);
+
+        let res = verify_chain(slice::from_ref(path), path, &KEY).unwrap();
+        assert!(res.problems.is_empty(), "{:?}", res.problems);
+        assert_eq!(res.records, AUDIT_CHECKPOINT_INTERVAL + 5);
+        assert_eq!(res.checkpoints, 1);
+    }
+
+    #[test]
+    fn modified_line_is_reported() {
+        let log = TestLog::new("modified");
+        let path = &log.path;
+        append(path, 0, 5);
+        let mut lines = read_lines(path);
+        lines[2] = lines[2].replace("\"n\":2", "\"n\":7");
+        write_lines(path, &lines);
+
+        assert_reported(&problems(path), "record 3 doesn't follow the record before it");
+    }
+
+    #[test]
+    fn removed_line_is_reported() {
+        let log = TestLog::new("removed");
+        let path = &log.path;
+        append(path, 0, 5);
+        let mut lines = read_lines(path);
+        lines.remove(2);
+        write_lines(path, &lines);
+
+        assert_reported(&problems(path), "record 2 is missing");
+    }
+
+    #[test]
+    fn truncation_is_reported() {
+        let log = TestLog::new("truncated");
+        let path = &log.path;
+        append(path, 0, 5);
+        let lines = read_lines(path);
+        write_lines(path, &lines[..3]);
+        assert_reported(&problems(path), "The log ends before record 4");
+
+        // Replacing the log with an empty one is truncation too
+        write_lines(path, &[]);
+        assert_reported(&problems(path), "The log ends before record 4");
+    }
+
+    #[test]
+    fn restarted_chain_is_reported() {
+        let log = TestLog::new("restarted");
+        let path = &log.path;
+        append(path, 0, 3);
+        fs::remove_file(head_path(path)).unwrap();
+        append(path, 3, 5);
+
+        assert_reported(&problems(path), "record 0 appears again");
+    }
+
+    #[test]
+    fn invalid_checkpoint_is_reported() {
+        let log = TestLog::new("checkpoint");
+        let path = &log.path;
+        append(path, 0, AUDIT_CHECKPOINT_INTERVAL - 1);
+        let mut lines = read_lines(path);
+        let last = lines.len() - 1;
+        let mut checkpoint: Value = serde_json::from_str(&lines[last]).unwrap();
+        checkpoint["checkpoint"]["mac"] = Value::from("00".repeat(32));
+        lines[last] = serde_json::to_string(&checkpoint).unwrap();
+        write_lines(path, &lines);
+
+        let seq = AUDIT_CHECKPOINT_INTERVAL - 1;
+        assert_reported(&problems(path), &format!("checkpoint at record {} has an invalid signature", seq));
+    }
+
+    #[test]
+    fn missing_head_is_reported() {
+        let log = TestLog::new("deleted");
+        let path = &log.path;
+        append(path, 0, 3);
+        fs::remove_file(path).unwrap();
+        fs::remove_file(head_path(path)).unwrap();
+
+        let res = verify_chain(slice::from_ref(path), path, &KEY).unwrap();
+        assert_reported(&res.problems, "the log is missing");
+
+        // The log can't silently start over either
+        let mut f = OpenOptions::new().append(true).create(true).open(path).unwrap();
+        assert!(append_chained(&mut f, path, &KEY, false, &serde_json::json!({ "n": 0 })).is_err());
+        assert_reported(&problems(path), "rudo.log.head is missing, the log was deleted or replaced");
+    }
+}
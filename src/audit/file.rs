@@ -0,0 +1,50 @@
+//!
+//! Audit sink appending one JSON line per record to a root-owned, hash-chained log file
+//!
+
+use std::fs::OpenOptions;
+use std::error::Error;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+
+use libc::{flock, LOCK_EX};
+
+use audit::AuditSink;
+use audit::AuditRecord;
+use audit::chain;
+
+/// Appends records to the file set by `audit_log`
+pub struct FileAuditSink {
+    path: String,
+}
+
+/// Append a record to the log at path. The log must be a regular file owned by root that
+/// nobody else can write to. Concurrent invocations are serialized with a lock.
+fn append_record(path: &str, record: &AuditRecord) -> Result<(), Box<dyn Error>> {
+    let mut f = OpenOptions::new().append(true).create(true).mode(0o600)
+        .custom_flags(libc::O_NOFOLLOW).open(path)?;
+    let metadata = f.metadata()?;
+    if !metadata.file_type().is_file() || metadata.uid() != 0 || metadata.mode() & 0o022 != 0
+        || metadata.nlink() != 1 {
+        return Err(From::from("it must be a regular file owned by root that only root can write to"));
+    }
+
+    if unsafe { flock(f.as_raw_fd(), LOCK_EX) } != 0 {
+        return Err(From::from("failed to lock it"));
+    }
+    chain::append_event(&mut f, path, record)
+}
+
+impl FileAuditSink {
+    pub fn new(path: &str) -> FileAuditSink {
+        FileAuditSink { path: path.to_string() }
+    }
+}
+
+impl AuditSink for FileAuditSink {
+    fn log(&self, record: &AuditRecord) -> Result<(), Box<dyn Error>> {
+        append_record(&self.path, record)
+            .map_err(|e| From::from(format!("Failed to write to audit log {}: {}", self.path, e)))
+    }
+}
@@ -0,0 +1,131 @@
+//!
+//! Audit sink sending structured entries to journald's native socket
+//!
+
+use std::io;
+use std::mem;
+use std::ptr;
+use std::process;
+use std::fs::File;
+use std::error::Error;
+use std::ffi::CString;
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::io::FromRawFd;
+use std::os::unix::net::UnixDatagram;
+
+use serde_json;
+use serde_json::Value;
+use libc::{c_int, c_void, msghdr, cmsghdr, sendmsg, memfd_create, fcntl, CMSG_SPACE, CMSG_LEN, CMSG_FIRSTHDR, CMSG_DATA};
+use libc::{MFD_CLOEXEC, MFD_ALLOW_SEALING, F_ADD_SEALS, F_SEAL_SHRINK, F_SEAL_GROW, F_SEAL_WRITE, F_SEAL_SEAL};
+use libc::{SOL_SOCKET, SCM_RIGHTS, EMSGSIZE, ENOBUFS};
+
+use audit::AuditSink;
+use audit::AuditRecord;
+use settings::SyslogSettings;
+
+use JOURNALD_SOCKET_PATH;
+
+/// Sends every record as a journal entry with the record's summary as its message and
+/// every field of the record as a `RUDO_<FIELD>` field, e.g. `RUDO_USER`
+pub struct JournaldAuditSink {
+    settings: SyslogSettings,
+}
+
+/// Append a field in journald's native format. Values containing a newline are sent
+/// as the name, a newline, the length as a little-endian u64 and then the raw value.
+fn append_field(buf: &mut Vec<u8>, name: &str, value: &str) {
+    buf.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        buf.push(b'\n');
+        buf.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        buf.push(b'=');
+    }
+    buf.extend_from_slice(value.as_bytes());
+    buf.push(b'\n');
+}
+
+impl JournaldAuditSink {
+    pub fn new(settings: SyslogSettings) -> JournaldAuditSink {
+        JournaldAuditSink { settings }
+    }
+
+    /// Encode a record as a journal entry
+    fn format_entry(&self, record: &AuditRecord) -> Result<Vec<u8>, Box<dyn Error>> {
+        let priority = if record.is_denied() { self.settings.deny_priority } else { self.settings.priority };
+
+        let mut buf = Vec::new();
+        append_field(&mut buf, "MESSAGE", &record.summary());
+        append_field(&mut buf, "PRIORITY", &priority.code().to_string());
+        append_field(&mut buf, "SYSLOG_FACILITY", &self.settings.facility.code().to_string());
+        append_field(&mut buf, "SYSLOG_IDENTIFIER", "rudo");
+        append_field(&mut buf, "SYSLOG_PID", &process::id().to_string());
+
+        // Strings are sent as they are, other values like the arguments as JSON.
+        // Missing values are left out.
+        if let Value::Object(fields) = serde_json::to_value(record)? {
+            for (name, value) in fields.iter() {
+                let name = format!("RUDO_{}", name.to_uppercase());
+                match *value {
+                    Value::Null => {},
+                    Value::String(ref s) => append_field(&mut buf, &name, s),
+                    ref v => append_field(&mut buf, &name, &v.to_string()),
+                }
+            }
+        }
+        Ok(buf)
+    }
+}
+
+/// Send an entry that's too large for a datagram the way journald's native protocol provides
+/// for: the entry is written to a sealed memfd, and only its descriptor is sent
+fn send_memfd(socket: &UnixDatagram, entry: &[u8]) -> Result<(), Box<dyn Error>> {
+    let name = CString::new("rudo-audit")?;
+    let fd = unsafe { memfd_create(name.as_ptr(), MFD_CLOEXEC | MFD_ALLOW_SEALING) };
+    if fd < 0 {
+        return Err(Box::new(io::Error::last_os_error()));
+    }
+    let mut memfd = unsafe { File::from_raw_fd(fd) };
+    memfd.write_all(entry)?;
+    if unsafe { fcntl(fd, F_ADD_SEALS, F_SEAL_SHRINK | F_SEAL_GROW | F_SEAL_WRITE | F_SEAL_SEAL) } < 0 {
+        return Err(Box::new(io::Error::last_os_error()));
+    }
+
+    unsafe {
+        // u64s keep the control buffer aligned for the cmsghdr
+        let space = CMSG_SPACE(mem::size_of::<c_int>() as u32) as usize;
+        let mut control = vec![0u64; space.div_ceil(mem::size_of::<u64>())];
+        let mut msg: msghdr = mem::zeroed();
+        msg.msg_control = control.as_mut_ptr() as *mut c_void;
+        msg.msg_controllen = space as _;
+
+        let cmsg: *mut cmsghdr = CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = SOL_SOCKET;
+        (*cmsg).cmsg_type = SCM_RIGHTS;
+        (*cmsg).cmsg_len = CMSG_LEN(mem::size_of::<c_int>() as u32) as _;
+        ptr::write_unaligned(CMSG_DATA(cmsg) as *mut c_int, memfd.as_raw_fd());
+
+        if sendmsg(socket.as_raw_fd(), &msg, 0) < 0 {
+            return Err(Box::new(io::Error::last_os_error()));
+        }
+    }
+    Ok(())
+}
+
+impl AuditSink for JournaldAuditSink {
+    fn log(&self, record: &AuditRecord) -> Result<(), Box<dyn Error>> {
+        let entry = self.format_entry(record)?;
+        let socket = UnixDatagram::unbound()?;
+        let res = socket.connect(JOURNALD_SOCKET_PATH).and_then(|_| socket.send(&entry)).map(|_| ());
+        let res: Result<(), Box<dyn Error>> = match res {
+            // Long arguments can make the entry too large for a single datagram
+            Err(ref e) if e.raw_os_error() == Some(EMSGSIZE) || e.raw_os_error() == Some(ENOBUFS) => {
+                send_memfd(&socket, &entry)
+            },
+            res => res.map_err(From::from),
+        };
+        res.map_err(|e| format!("Failed to send audit event to {}: {}", JOURNALD_SOCKET_PATH, e))?;
+        Ok(())
+    }
+}
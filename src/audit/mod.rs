@@ -0,0 +1,170 @@
+//!
+//! Audit events for every command or shell rudo is asked to run
+//!
+
+use std::env;
+use std::error::Error;
+
+use time;
+
+use settings::Settings;
+use settings::AuditSinkType;
+use osutils::OSUtils;
+use session::get_cur_tty_name;
+
+pub mod chain;
+mod file;
+mod syslog;
+#[cfg(target_os = "linux")]
+mod journald;
+use self::file::FileAuditSink;
+use self::syslog::SyslogAuditSink;
+#[cfg(target_os = "linux")]
+use self::journald::JournaldAuditSink;
+
+/// Outcome of authenticating the invoking user
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthResult {
+    Success,
+    Failure, // Wrong credentials on every try
+    Error,   // Authentication couldn't be completed, e.g. because of a lockout or missing input
+}
+
+/// Whether the policy allows the request
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Decision {
+    Allow,
+    Deny,
+}
+
+/// A single line of the audit log
+#[derive(Serialize, Clone)]
+pub struct AuditRecord {
+    pub timestamp: String,
+    pub user: String,                 // User that ran rudo
+    pub uid: u32,
+    pub tty: Option<String>,
+    pub cwd: Option<String>,
+    pub host: Option<String>,
+    pub command: String,              // Command as given by the user, or the shell for -i and -s
+    pub args: Vec<String>,
+    pub path: Option<String>,         // Resolved path of the command, if it's allowed
+    pub target_user: String,
+    pub target_group: Option<String>,
+    pub auth: Option<AuthResult>,
+    pub decision: Option<Decision>,
+    pub rule: Option<String>,         // Rule that allowed the command, see Settings::describe_rule
+    pub tag: Option<String>,          // Tag of that rule
+    pub reason: Option<String>,       // Why the command was denied
+    pub completed: bool,              // Written when the command exits, after the record that allowed it
+    pub exit_status: Option<i32>,     // Only known if rudo waited for the command
+    pub signal: Option<i32>,          // Signal that killed the command, if any
+    pub timed_out: bool,              // The command was stopped after its rule's timeout_sec
+}
+
+impl AuditRecord {
+    /// Start a record of the current invocation. The caller fills in the outcome.
+    pub fn new<T: OSUtils>(osutils: &T, command: &str, args: &[String], target_user: &str,
+                           target_group: Option<String>) -> Result<AuditRecord, Box<dyn Error>> {
+        Ok(AuditRecord {
+            timestamp: time::OffsetDateTime::now_utc().format("%FT%TZ"),
+            user: osutils.get_username()?,
+            uid: osutils.get_current_uid()?,
+            tty: get_cur_tty_name().unwrap_or(None),
+            cwd: env::current_dir().ok().map(|d| d.to_string_lossy().into_owned()),
+            host: osutils.get_hostname().ok(),
+            command: command.to_string(),
+            args: args.to_vec(),
+            path: None,
+            target_user: target_user.to_string(),
+            target_group,
+            auth: None,
+            decision: None,
+            rule: None,
+            tag: None,
+            reason: None,
+            completed: false,
+            exit_status: None,
+            signal: None,
+            timed_out: false,
+        })
+    }
+}
+
+impl AuditRecord {
+    /// Whether the request was turned down, either by the policy or because the user
+    /// couldn't authenticate
+    pub fn is_denied(&self) -> bool {
+        self.decision != Some(Decision::Allow) || self.auth != Some(AuthResult::Success)
+    }
+
+    /// Describe the event in a single line, like sudo does in syslog
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    pub fn summary(&self) -> String {
+        let outcome = match (self.auth, self.decision) {
+            (Some(AuthResult::Failure), _) => String::from("authentication failure"),
+            (Some(AuthResult::Error), _) => String::from("authentication error"),
+            (_, Some(Decision::Deny)) => format!("denied ({})", self.reason.as_deref().unwrap_or("no reason")),
+            _ if self.completed => String::from("completed"),
+            _ => String::from("allowed"),
+        };
+        let mut command = self.path.clone().unwrap_or_else(|| self.command.clone());
+        for arg in self.args.iter() {
+            command += " ";
+            command += arg;
+        }
+
+        let mut summary = format!("{} : {} ; TTY={} ; PWD={} ; USER={}", self.user, outcome,
+                                  self.tty.as_deref().unwrap_or("unknown"), self.cwd.as_deref().unwrap_or("unknown"),
+                                  self.target_user);
+        if let Some(ref group) = self.target_group {
+            summary += &format!(" ; GROUP={}", group);
+        }
+        summary += &format!(" ; COMMAND={}", command);
+        if let Some(status) = self.exit_status {
+            summary += &format!(" ; EXIT={}", status);
+        }
+        if let Some(sig) = self.signal {
+            summary += &format!(" ; SIGNAL={}", sig);
+        }
+        if self.timed_out {
+            summary += " ; TIMED_OUT";
+        }
+        summary
+    }
+}
+
+/// Destination of audit records
+pub trait AuditSink {
+    /// Send a record. Every record is sent on its own, so concurrent invocations never mix them.
+    fn log(&self, record: &AuditRecord) -> Result<(), Box<dyn Error>>;
+}
+
+/// Get the sinks selected in the settings
+fn get_sinks(settings: &Settings) -> Vec<Box<dyn AuditSink>> {
+    settings.audit_sinks.iter().map(|sink| -> Box<dyn AuditSink> {
+        match *sink {
+            AuditSinkType::File => Box::new(FileAuditSink::new(&settings.audit_log)),
+            AuditSinkType::Syslog => Box::new(SyslogAuditSink::new(settings.syslog)),
+            #[cfg(target_os = "linux")]
+            AuditSinkType::Journald => Box::new(JournaldAuditSink::new(settings.syslog)),
+            #[cfg(not(target_os = "linux"))]
+            AuditSinkType::Journald => unreachable!("Settings::validate rejects journald off Linux"),
+        }
+    }).collect()
+}
+
+/// Send a record to every audit sink. Every sink is tried, even if an earlier one fails.
+pub fn log(settings: &Settings, record: &AuditRecord) -> Result<(), Box<dyn Error>> {
+    let mut res = Ok(());
+    for sink in get_sinks(settings) {
+        if let Err(e) = sink.log(record) {
+            if res.is_ok() {
+                res = Err(e);
+            }
+        }
+    }
+    res
+}
@@ -0,0 +1,82 @@
+//!
+//! Audit sink sending RFC 5424 messages to the local syslog socket
+//!
+
+use std::process;
+use std::error::Error;
+use std::os::unix::net::UnixDatagram;
+
+use serde_json;
+
+use audit::AuditSink;
+use audit::AuditRecord;
+use settings::SyslogSettings;
+
+use SYSLOG_SOCKET_PATH;
+
+/// Most bytes of arguments sent in a message. Every message is a single datagram, so long
+/// arguments could make it too large to send, and syslog daemons cut off long messages anyway.
+const MAX_ARGS_LEN: usize = 4096;
+
+/// What's appended to arguments that were cut off
+static TRUNCATED_MARKER: &str = "...";
+
+/// Sends every record as a message with the record's JSON as its text
+pub struct SyslogAuditSink {
+    settings: SyslogSettings,
+}
+
+/// Cut the arguments down to MAX_ARGS_LEN bytes in total. The argument that crosses the limit
+/// is cut off with TRUNCATED_MARKER and the ones after it are left out.
+fn truncate_args(args: &[String]) -> Vec<String> {
+    let mut truncated = Vec::new();
+    let mut len = 0;
+    for arg in args {
+        if len + arg.len() <= MAX_ARGS_LEN {
+            truncated.push(arg.clone());
+            len += arg.len();
+            continue;
+        }
+        let mut end = MAX_ARGS_LEN - len;
+        while !arg.is_char_boundary(end) {
+            end -= 1;
+        }
+        truncated.push(format!("{}{}", &arg[..end], TRUNCATED_MARKER));
+        break;
+    }
+    truncated
+}
+
+impl SyslogAuditSink {
+    pub fn new(settings: SyslogSettings) -> SyslogAuditSink {
+        SyslogAuditSink { settings }
+    }
+
+    /// Format a record as an RFC 5424 message:
+    /// `<PRI>1 TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA MSG`
+    fn format_message(&self, record: &AuditRecord) -> Result<String, Box<dyn Error>> {
+        let priority = if record.is_denied() { self.settings.deny_priority } else { self.settings.priority };
+        let pri = self.settings.facility.code() * 8 + priority.code();
+
+        // Header fields can't be empty or contain spaces, `-` stands for a missing value
+        let host = record.host.as_deref().filter(|h| !h.is_empty() && !h.contains(' ')).unwrap_or("-");
+        let json = if record.args.iter().map(|a| a.len()).sum::<usize>() > MAX_ARGS_LEN {
+            let mut record = record.clone();
+            record.args = truncate_args(&record.args);
+            serde_json::to_string(&record)?
+        } else {
+            serde_json::to_string(record)?
+        };
+        Ok(format!("<{}>1 {} {} rudo {} audit - {}", pri, record.timestamp, host, process::id(), json))
+    }
+}
+
+impl AuditSink for SyslogAuditSink {
+    fn log(&self, record: &AuditRecord) -> Result<(), Box<dyn Error>> {
+        let message = self.format_message(record)?;
+        let socket = UnixDatagram::unbound()?;
+        socket.send_to(message.as_bytes(), SYSLOG_SOCKET_PATH)
+            .map_err(|e| format!("Failed to send audit event to {}: {}", SYSLOG_SOCKET_PATH, e))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,379 @@
+//!
+//! Reading passwords and codes from the user
+//!
+
+use std::env;
+use std::io;
+use std::error::Error;
+use std::io::Read;
+use std::io::Write;
+use std::process::Child;
+use std::process::Command;
+use std::process::ExitStatus;
+use std::process::Stdio;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::process::CommandExt;
+
+use termion::get_tty;
+use libc::{c_void, read, STDIN_FILENO};
+use zeroize::Zeroize;
+use zeroize::Zeroizing;
+
+use settings::Settings;
+use osutils::OSUtils;
+use auth::AuthError;
+use auth::tty::TtyGuard;
+use auth::tty::wait_readable;
+
+// Characters that erase the last character in addition to the terminal's erase character
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7f;
+
+/// Longest password that can be entered, matching PAM_MAX_RESP_SIZE. Buffers are allocated
+/// with this capacity up front so they never reallocate and leave copies behind.
+const MAX_PASSWORD_LEN: usize = 512;
+
+/// Environment variable naming the askpass helper, used if the config doesn't set one
+pub static ASKPASS_ENV: &str = "RUDO_ASKPASS";
+
+/// Where the user asked for passwords to be read from on the command line
+#[derive(Clone, Copy, PartialEq)]
+pub enum InputMode {
+    Tty,            // Default
+    Askpass,        // -A
+    Stdin,          // -S
+    NonInteractive, // -n
+}
+
+/// Where passwords are read from
+enum PasswordSource {
+    /// The controlling TTY, even if stdin/stdout are redirected
+    Tty,
+    /// Stdout of a helper program run with the invoking user's uid and gid
+    Askpass { helper: String, uid: u32, gid: u32 },
+    /// A single line of stdin, with the prompt written to stderr
+    Stdin,
+    /// Nowhere. Any attempt to prompt fails with `AuthError::InteractionRequired`.
+    NonInteractive,
+}
+
+/// Reads passwords from wherever the user asked for
+pub struct PasswordInput {
+    source: PasswordSource,
+    timeout: Option<Duration>, // How long a prompt may wait for input, if limited
+    feedback: bool,            // Print a `*` for every character typed at the TTY
+}
+
+impl PasswordInput {
+    /// Pick the input for this invocation. The askpass helper is taken from the config
+    /// first, falling back to the environment.
+    pub fn new<T: OSUtils>(osutils: &T, settings: &Settings, mode: InputMode) -> Result<PasswordInput, Box<dyn Error>> {
+        let source = match mode {
+            InputMode::Tty => PasswordSource::Tty,
+            InputMode::Stdin => PasswordSource::Stdin,
+            InputMode::NonInteractive => PasswordSource::NonInteractive,
+            InputMode::Askpass => {
+                let helper = match settings.askpass {
+                    Some(ref v) => v.clone(),
+                    None => env::var(ASKPASS_ENV)
+                        .map_err(|_| format!("No askpass helper configured! Set askpass in the config or {}.",
+                                             ASKPASS_ENV))?,
+                };
+                if !helper.starts_with('/') {
+                    return Err(From::from("The askpass helper must be an absolute path!"));
+                }
+
+                PasswordSource::Askpass {
+                    helper,
+                    uid: osutils.get_current_uid()?,
+                    gid: osutils.get_current_gid()?,
+                }
+            },
+        };
+
+        let timeout = if settings.password_timeout_sec > 0 {
+            Some(Duration::from_secs(settings.password_timeout_sec))
+        } else {
+            None
+        };
+
+        Ok(PasswordInput { source, timeout, feedback: settings.pwfeedback })
+    }
+
+    /// Show the prompt and read a single line of input without echoing it.
+    /// The returned password is wiped from memory when dropped.
+    pub fn read_password(&self, prompt: &str) -> Result<Zeroizing<String>, Box<dyn Error>> {
+        let deadline = self.timeout.map(|t| Instant::now() + t);
+        match self.source {
+            PasswordSource::Tty => read_password_tty(prompt, deadline, self.feedback),
+            PasswordSource::Askpass { ref helper, uid, gid } => read_password_askpass(helper, uid, gid, prompt, deadline),
+            PasswordSource::Stdin => read_password_stdin(prompt, deadline),
+            PasswordSource::NonInteractive => Err(Box::new(AuthError::InteractionRequired)),
+        }
+    }
+
+    /// Whether the user may be prompted at all
+    pub fn is_interactive(&self) -> bool {
+        !matches!(self.source, PasswordSource::NonInteractive)
+    }
+}
+
+/// Convert a password buffer to a string without leaving a copy behind if it isn't UTF-8
+fn into_password_string(buf: Vec<u8>) -> Result<Zeroizing<String>, Box<dyn Error>> {
+    match String::from_utf8(buf) {
+        Ok(v) => Ok(Zeroizing::new(v)),
+        Err(e) => {
+            e.into_bytes().zeroize();
+            Err(From::from("Password isn't valid UTF-8!"))
+        }
+    }
+}
+
+/// Function to read in a password from the controlling TTY
+/// Works even if stdin/stdout are redirected
+fn read_password_tty(prompt: &str, deadline: Option<Instant>, feedback: bool)
+    -> Result<Zeroizing<String>, Box<dyn Error>> {
+    let mut tty = get_tty()?;
+    tty.write_all(prompt.as_bytes())?;
+
+    // Put the TTY into a mode where it doesn't echo the user's keystrokes back.
+    // The guard restores the original mode on every way out of this block.
+    let res: Result<Zeroizing<String>, Box<dyn Error>>;
+    {
+        let guard = TtyGuard::new(tty.as_raw_fd(), !feedback)?;
+        res = if feedback {
+            read_line_feedback(&mut tty, &guard, deadline)
+        } else {
+            read_line_canonical(&mut tty, deadline)
+        };
+    }
+
+    // Write a newline and return the result
+    tty.write_all(b"\n")?;
+    res
+}
+
+/// Read a line from a TTY in canonical mode, where the terminal handles line editing.
+/// Ctrl-D on an empty line cancels the prompt.
+fn read_line_canonical<R: Read + AsRawFd>(tty: &mut R, deadline: Option<Instant>)
+    -> Result<Zeroizing<String>, Box<dyn Error>> {
+    let mut line = Zeroizing::new(Vec::with_capacity(MAX_PASSWORD_LEN));
+    let mut chunk = Zeroizing::new([0u8; MAX_PASSWORD_LEN]);
+    loop {
+        wait_readable(tty.as_raw_fd(), deadline)?;
+        let n = tty.read(&mut chunk[..])?;
+        if n == 0 {
+            // End of file. Either Ctrl-D on an empty line, or after some input without a newline.
+            if line.is_empty() {
+                return Err(Box::new(AuthError::Cancelled));
+            }
+            break;
+        }
+
+        if line.len() + n > MAX_PASSWORD_LEN {
+            return Err(From::from("Password is too long!"));
+        }
+        line.extend_from_slice(&chunk[..n]);
+        if line.last() == Some(&b'\n') {
+            line.pop();
+            break;
+        }
+    }
+
+    into_password_string(std::mem::take(&mut *line))
+}
+
+/// Read a line from a TTY one keystroke at a time, printing a `*` for every character.
+/// Erase, kill and word erase are handled with the terminal's configured characters.
+/// Ctrl-D on an empty line cancels the prompt.
+fn read_line_feedback<T: Read + Write + AsRawFd>(tty: &mut T, guard: &TtyGuard, deadline: Option<Instant>)
+    -> Result<Zeroizing<String>, Box<dyn Error>> {
+    let edit = guard.get_edit_chars();
+    let mut line = Zeroizing::new(Vec::with_capacity(MAX_PASSWORD_LEN));
+    let mut byte = Zeroizing::new([0u8; 1]);
+
+    // Remove the last character from the line, including all bytes of a multibyte one,
+    // and erase its `*`. Returns whether there was a character to remove.
+    fn erase_char<W: Write>(line: &mut Vec<u8>, tty: &mut W) -> Result<bool, Box<dyn Error>> {
+        let mut removed = false;
+        while let Some(b) = line.pop() {
+            removed = true;
+            if b & 0xc0 != 0x80 {
+                break;
+            }
+        }
+        if removed {
+            tty.write_all(b"\x08 \x08")?;
+        }
+        Ok(removed)
+    }
+
+    loop {
+        wait_readable(tty.as_raw_fd(), deadline)?;
+        if tty.read(&mut byte[..])? == 0 {
+            return Err(Box::new(AuthError::Cancelled));
+        }
+
+        let b = byte[0];
+        if b == b'\r' || b == b'\n' {
+            break;
+        } else if b == edit.eof {
+            if line.is_empty() {
+                return Err(Box::new(AuthError::Cancelled));
+            }
+            break;
+        } else if b == edit.erase || b == BACKSPACE || b == DELETE {
+            erase_char(&mut line, tty)?;
+        } else if b == edit.kill {
+            while erase_char(&mut line, tty)? {}
+        } else if b == edit.werase {
+            // Erase trailing whitespace, then the word before it
+            while line.last().is_some_and(|c| c.is_ascii_whitespace()) {
+                erase_char(&mut line, tty)?;
+            }
+            while line.last().is_some_and(|c| !c.is_ascii_whitespace()) {
+                erase_char(&mut line, tty)?;
+            }
+        } else {
+            if line.len() >= MAX_PASSWORD_LEN {
+                return Err(From::from("Password is too long!"));
+            }
+            line.push(b);
+
+            // Only the first byte of a multibyte character gets a `*`
+            if b & 0xc0 != 0x80 {
+                tty.write_all(b"*")?;
+            }
+        }
+    }
+
+    into_password_string(std::mem::take(&mut *line))
+}
+
+/// Run the askpass helper with the prompt as its only argument and read the password
+/// from the first line of its stdout. The helper never runs with root privileges, and
+/// is killed if it hasn't finished by the deadline.
+fn read_password_askpass(helper: &str, uid: u32, gid: u32, prompt: &str, deadline: Option<Instant>)
+    -> Result<Zeroizing<String>, Box<dyn Error>> {
+    if uid == 0 {
+        // Already root, so there's no unprivileged user to run the helper as
+        return Err(From::from("The askpass helper can't be used by root!"));
+    }
+
+    let mut child = Command::new(helper)
+        .arg(prompt)
+        .uid(uid)
+        .gid(gid)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()?;
+
+    let mut buf = Zeroizing::new([0u8; MAX_PASSWORD_LEN + 1]);
+    let res = match child.stdout.take() {
+        Some(mut stdout) => read_helper_output(&mut stdout, &mut buf, deadline),
+        None => Err(From::from("The askpass helper has no stdout!")),
+    };
+    let res = res.and_then(|len| wait_helper(&mut child, deadline).map(|status| (len, status)));
+    let (len, status) = match res {
+        Ok(v) => v,
+        Err(e) => {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(e);
+        },
+    };
+
+    if !status.success() {
+        return Err(From::from("The askpass helper was cancelled or failed!"));
+    }
+
+    let line_len = buf[..len].iter().position(|&b| b == b'\n').unwrap_or(len);
+    let mut line = Zeroizing::new(Vec::with_capacity(line_len));
+    line.extend_from_slice(&buf[..line_len]);
+    into_password_string(std::mem::take(&mut *line))
+}
+
+/// Wait for the helper to exit, giving up once the deadline passes
+fn wait_helper(child: &mut Child, deadline: Option<Instant>) -> Result<ExitStatus, Box<dyn Error>> {
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        if deadline.is_some_and(|d| Instant::now() >= d) {
+            return Err(Box::new(AuthError::Timeout));
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Read the helper's output into buf until it closes its stdout, and return its length.
+/// The output can't be longer than a password and its newline.
+fn read_helper_output<R: Read + AsRawFd>(stdout: &mut R, buf: &mut [u8; MAX_PASSWORD_LEN + 1],
+    deadline: Option<Instant>) -> Result<usize, Box<dyn Error>> {
+    let mut len = 0;
+    loop {
+        wait_readable(stdout.as_raw_fd(), deadline)?;
+        let n = match stdout.read(&mut buf[len..]) {
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(Box::new(e)),
+        };
+        if n == 0 {
+            return Ok(len);
+        }
+        len += n;
+
+        // Anything after the first line is ignored once the buffer is full
+        if len == buf.len() {
+            if !buf.contains(&b'\n') {
+                return Err(From::from("Password is too long!"));
+            }
+            return Ok(len);
+        }
+    }
+}
+
+/// Read a password from the first line of stdin. Used by scripts that pipe the password in.
+/// Stdin is read directly a byte at a time, so no buffer keeps a copy of the password and
+/// the rest of the input is left for the command.
+fn read_password_stdin(prompt: &str, deadline: Option<Instant>) -> Result<Zeroizing<String>, Box<dyn Error>> {
+    write!(&mut io::stderr(), "{}", prompt)?;
+
+    let mut line = Zeroizing::new(Vec::with_capacity(MAX_PASSWORD_LEN));
+    let mut byte = Zeroizing::new([0u8; 1]);
+    loop {
+        wait_readable(STDIN_FILENO, deadline)?;
+        let n = unsafe { read(STDIN_FILENO, byte.as_mut_ptr() as *mut c_void, 1) };
+        if n < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(Box::new(err));
+        }
+        if n == 0 {
+            if line.is_empty() {
+                return Err(Box::new(AuthError::NoInput));
+            }
+            break;
+        }
+
+        if byte[0] == b'\n' {
+            break;
+        }
+        if line.len() == MAX_PASSWORD_LEN {
+            return Err(From::from("Password is too long!"));
+        }
+        line.push(byte[0]);
+    }
+
+    // Strip a carriage return from a CRLF line ending
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    into_password_string(std::mem::take(&mut *line))
+}
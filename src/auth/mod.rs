@@ -2,13 +2,27 @@ use std::error::Error;
 use std::io::prelude::*;
 use std::io;
 use std::cmp;
+use std::fmt;
 
 use session::create_session;
 use session::check_session;
 
+use lockout::get_lockout_remaining;
+use lockout::record_attempt;
+use lockout::withdraw_attempt;
+use lockout::record_failure;
+use lockout::clear_failures;
+
 use settings::Settings;
+use settings::Rule;
+use settings::AuthEntry;
+use settings::ControlFlag;
 use osutils::OSUtils;
 
+pub mod input;
+mod tty;
+use self::input::PasswordInput;
+
 #[cfg(feature = "pam")]
 pub mod pam;
 #[cfg(feature = "pam")]
@@ -19,6 +33,33 @@ pub mod touchid;
 #[cfg(feature = "touchid")]
 use self::touchid::*;
 
+#[cfg(feature = "totp")]
+pub mod totp;
+#[cfg(feature = "totp")]
+use self::totp::*;
+
+/// Authentication errors that callers need to tell apart from a failed authentication
+#[derive(Debug)]
+pub enum AuthError {
+    InteractionRequired, // The user would have to be prompted, but that isn't allowed
+    NoInput,             // Input for a prompt ended before anything was read
+    Cancelled,           // The user cancelled a prompt with Ctrl-C or Ctrl-D
+    Timeout,             // Nothing was entered at a prompt within password_timeout_sec
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AuthError::InteractionRequired => write!(f, "a password is required"),
+            AuthError::NoInput => write!(f, "no password was provided"),
+            AuthError::Cancelled => write!(f, "cancelled"),
+            AuthError::Timeout => write!(f, "timed out waiting for a password"),
+        }
+    }
+}
+
+impl Error for AuthError {}
+
 /// Interface for authentication frameworks
 pub trait AuthFramework {
     fn authenticate(&self) -> Result<bool, Box<dyn Error>>;
@@ -26,62 +67,305 @@ pub trait AuthFramework {
     fn get_name(&self) -> &'static str;
 }
 
-pub fn authenticate_current_user_n<T: OSUtils>(osutils: &T, settings: &Settings, n: i32)
+/// Counts attempts towards the invoking user's lockout. Every attempt is recorded before it's
+/// made, and withdrawn if it succeeds or can't be completed, see lockout::record_attempt
+trait AttemptCounter {
+    fn record_attempt(&self) -> Result<bool, Box<dyn Error>>;
+    fn withdraw_attempt(&self) -> Result<(), Box<dyn Error>>;
+    fn record_failure(&self) -> Result<bool, Box<dyn Error>>;
+}
+
+/// Counts attempts in the user's failure record
+struct UserAttempts<'a> {
+    username: &'a str,
+    settings: &'a Settings,
+}
+
+impl<'a> AttemptCounter for UserAttempts<'a> {
+    fn record_attempt(&self) -> Result<bool, Box<dyn Error>> {
+        record_attempt(self.username, self.settings)
+    }
+
+    fn withdraw_attempt(&self) -> Result<(), Box<dyn Error>> {
+        withdraw_attempt(self.username, self.settings)
+    }
+
+    fn record_failure(&self) -> Result<bool, Box<dyn Error>> {
+        record_failure(self.username, self.settings)
+    }
+}
+
+/// Authenticate the current user with the auth chain of the given rule, or the
+/// global chain if there is no rule. The user has to present the credentials of `auth_user`.
+/// Sessions only cover commands run as the same `target_user` by rules with the same tag.
+pub fn authenticate_current_user_n<T: OSUtils>(osutils: &T, settings: &Settings, rule: Option<&Rule>,
+                                               auth_user: &str, target_user: &str, input: &PasswordInput, n: i32)
     -> Result<bool, Box<dyn Error>> {
 
+    // Rules with their own auth chain always require a fresh authentication, since
+    // a session may have been created with a weaker chain
+    let overrides_chain = rule.is_some_and(|r| r.auth_chain.is_some());
+    let tag = rule.and_then(|r| r.tag.as_deref());
+
     // If the user already has a valid session, skip authentication
     let username = osutils.get_username()?;
-    let has_session = check_session(&username)?;
-    if has_session { return Ok(true); }
-
-    // Instantiate all supported frameworks
-    let mut frameworks: Vec<Box<dyn AuthFramework>> = Vec::new();
-    
-    #[cfg(feature = "touchid")]
-    {
-        frameworks.push(Box::new(TouchIDAuthFramework::<T>::new(osutils, settings)));
-    }
-    
-    #[cfg(feature = "pam")]
-    {
-        frameworks.push(Box::new(PamAuthFramework::<T>::new(osutils, settings)));
-    } 
-
-    let mut authenticated: bool = false;
-    for f in frameworks.iter() {
-        // Determine maximum number of attempts for this framework
-        let mut max_tries = f.get_max_tries();
-        if max_tries == 0 {
-            max_tries = n;
-        } else {
-            max_tries = cmp::min(max_tries, n);
+    if !overrides_chain {
+        let has_session = check_session(settings, &username, auth_user, target_user, tag)?;
+        if has_session { return Ok(true); }
+    }
+
+    let authenticated = authenticate_chain_n(osutils, settings, settings.get_auth_chain(rule), auth_user, input, n)?;
+
+    // If authentication was successful, crate a new session
+    if authenticated && !overrides_chain && settings.session_timeout_sec > 0 {
+        create_session(settings, &username, auth_user, target_user, tag)?;
+    }
+
+    Ok(authenticated)
+}
+
+/// Instantiate the framework with the given name, authenticating as auth_user.
+/// Only PAM needs every argument, so builds without it leave some of them unused.
+#[cfg_attr(not(feature = "pam"), allow(unused_variables))]
+fn get_framework<'a, T: OSUtils>(name: &str, osutils: &'a T, settings: &'a Settings, auth_user: &'a str,
+                                 input: &'a PasswordInput)
+    -> Result<Box<dyn AuthFramework + 'a>, Box<dyn Error>> {
+    match name {
+        #[cfg(feature = "pam")]
+        "pam" => Ok(Box::new(PamAuthFramework::<T>::new(osutils, settings, auth_user, input))),
+        #[cfg(feature = "touchid")]
+        "touchid" => Ok(Box::new(TouchIDAuthFramework::<T>::new(osutils, settings, auth_user))),
+        #[cfg(feature = "totp")]
+        "totp" => Ok(Box::new(TotpAuthFramework::new(auth_user, input))),
+        _ => Err(From::from(format!("Authentication framework {} is not available in this build!", name))),
+    }
+}
+
+/// Run the current user through the given auth chain as auth_user without consulting or creating
+/// sessions. Control flags behave like PAM's: the chain succeeds if no required or requisite framework
+/// failed and at least one framework succeeded. A failed required framework doesn't end the chain, so
+/// it isn't given away which one failed. Failures count against the invoking user's lockout.
+pub fn authenticate_chain_n<T: OSUtils>(osutils: &T, settings: &Settings, chain: &[AuthEntry],
+                                        auth_user: &str, input: &PasswordInput, n: i32)
+    -> Result<bool, Box<dyn Error>> {
+
+    // Without a valid session, non-interactive callers fail before anything is attempted
+    if !input.is_interactive() {
+        return Err(Box::new(AuthError::InteractionRequired));
+    }
+
+    // Instantiate all frameworks up front so a misconfigured chain fails before any prompt
+    let mut frameworks: Vec<(Box<dyn AuthFramework>, ControlFlag)> = Vec::new();
+    for entry in chain {
+        frameworks.push((get_framework(&entry.framework, osutils, settings, auth_user, input)?, entry.control));
+    }
+
+    let username = osutils.get_username()?;
+    let attempts = UserAttempts { username: &username, settings };
+    let mut any_succeeded = false;
+    let mut required_failed = false;
+    for (f, control) in frameworks.iter() {
+        // Stop as soon as the user is locked out, even in the middle of the chain
+        let lockout_remaining = get_lockout_remaining(&username, settings)?;
+        if lockout_remaining > 0 {
+            writeln!(&mut io::stderr(), "Too many failed attempts. Try again in {} seconds.", lockout_remaining)?;
+            return Ok(false);
         }
 
-        // Try to authenticate using this framework
-        for i in 0..max_tries {
-            let res = f.authenticate()?;
-            if res {
-                authenticated = true;
+        // A framework that can't be used at all (e.g. no enrolled TOTP secret) counts as a failure,
+        // unless the input itself failed, which the caller has to know about
+        let res = match try_framework(f.as_ref(), &attempts, n) {
+            Ok(v) => v,
+            Err(e) => {
+                if e.is::<AuthError>() {
+                    return Err(e);
+                }
+                writeln!(&mut io::stderr(), "{}: {}", f.get_name(), e)?;
+                false
+            }
+        };
+
+        match (*control, res) {
+            (ControlFlag::Requisite, false) => return Ok(false),
+            (ControlFlag::Required, false) => required_failed = true,
+            (ControlFlag::Sufficient, true) if !required_failed => {
+                any_succeeded = true;
                 break;
+            },
+            (_, true) => any_succeeded = true,
+            (_, false) => {},
+        }
+    }
+
+    if required_failed {
+        return Ok(false);
+    }
+    if any_succeeded {
+        clear_failures(&username, settings)?;
+    }
+    Ok(any_succeeded)
+}
+
+/// Give the user up to `n` tries to authenticate with the given framework,
+/// or fewer if the framework has its own limit. Every failed try is recorded
+/// towards the user's lockout threshold.
+fn try_framework(f: &dyn AuthFramework, attempts: &dyn AttemptCounter, n: i32) -> Result<bool, Box<dyn Error>> {
+    // Determine maximum number of attempts for this framework
+    let mut max_tries = f.get_max_tries();
+    if max_tries == 0 {
+        max_tries = n;
+    } else {
+        max_tries = cmp::min(max_tries, n);
+    }
+
+    // Try to authenticate using this framework
+    for i in 0..max_tries {
+        // The attempt is counted up front and withdrawn if it succeeds
+        if attempts.record_attempt()? {
+            return Ok(false);
+        }
+
+        let res = match f.authenticate() {
+            Ok(v) => v,
+            Err(e) => {
+                // The attempt wasn't completed, e.g. the prompt was cancelled or timed out
+                attempts.withdraw_attempt()?;
+                return Err(e);
+            },
+        };
+        if res {
+            attempts.withdraw_attempt()?;
+            return Ok(true);
+        }
+
+        if attempts.record_failure()? {
+            return Ok(false);
+        }
+
+        if i != max_tries - 1 {
+            writeln!(&mut io::stderr(), "Invalid credentials. Try again.")?;
+        }
+    }
+
+    writeln!(&mut io::stderr(), "Failed to authenticate with {}.", f.get_name())?;
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::{Cell, RefCell};
+
+    /// What a try of the fake framework does
+    #[derive(Clone, Copy)]
+    enum Outcome {
+        Success,
+        Failure,
+        Cancelled,
+        Unusable, // The framework can't be used at all
+    }
+
+    /// Framework that goes through the given outcomes, one per try
+    struct FakeFramework {
+        outcomes: RefCell<Vec<Outcome>>,
+        tries: Cell<usize>,
+    }
+
+    impl FakeFramework {
+        fn new(outcomes: &[Outcome]) -> FakeFramework {
+            FakeFramework { outcomes: RefCell::new(outcomes.to_vec()), tries: Cell::new(0) }
+        }
+    }
+
+    impl AuthFramework for FakeFramework {
+        fn authenticate(&self) -> Result<bool, Box<dyn Error>> {
+            self.tries.set(self.tries.get() + 1);
+            match self.outcomes.borrow_mut().remove(0) {
+                Outcome::Success => Ok(true),
+                Outcome::Failure => Ok(false),
+                Outcome::Cancelled => Err(Box::new(AuthError::Cancelled)),
+                Outcome::Unusable => Err(From::from("not enrolled")),
             }
+        }
+
+        fn get_max_tries(&self) -> i32 {
+            0
+        }
+
+        fn get_name(&self) -> &'static str {
+            "fake"
+        }
+    }
+
+    /// Failure count that locks the user out once it reaches max_failures
+    struct FakeAttempts {
+        failures: Cell<u32>,
+        max_failures: u32,
+    }
+
+    impl FakeAttempts {
+        fn new(max_failures: u32) -> FakeAttempts {
+            FakeAttempts { failures: Cell::new(0), max_failures }
+        }
+    }
 
-            if i != max_tries - 1 {
-                writeln!(&mut io::stderr(), "Invalid credentials. Try again.")?;
+    impl AttemptCounter for FakeAttempts {
+        fn record_attempt(&self) -> Result<bool, Box<dyn Error>> {
+            if self.failures.get() >= self.max_failures {
+                return Ok(true);
             }
+            self.failures.set(self.failures.get() + 1);
+            Ok(false)
         }
 
-        // If authentication succeeded, break
-        if authenticated { 
-            break; 
-        } else {
-            writeln!(&mut io::stderr(), "Failed to authenticate with {}.", f.get_name())?;
+        fn withdraw_attempt(&self) -> Result<(), Box<dyn Error>> {
+            self.failures.set(self.failures.get() - 1);
+            Ok(())
+        }
+
+        fn record_failure(&self) -> Result<bool, Box<dyn Error>> {
+            Ok(self.failures.get() >= self.max_failures)
         }
     }
 
-    // If authentication was successful, crate a new session
-    if authenticated && settings.session_timeout_sec > 0 {
-        create_session(&username, settings.session_timeout_sec)?;
+    #[test]
+    fn failed_tries_are_counted() {
+        let f = FakeFramework::new(&[Outcome::Failure, Outcome::Failure, Outcome::Failure]);
+        let attempts = FakeAttempts::new(10);
+        assert!(!try_framework(&f, &attempts, 3).unwrap());
+        assert_eq!(attempts.failures.get(), 3);
     }
 
-    Ok(authenticated)
+    #[test]
+    fn successful_tries_are_withdrawn() {
+        let f = FakeFramework::new(&[Outcome::Failure, Outcome::Success]);
+        let attempts = FakeAttempts::new(10);
+        assert!(try_framework(&f, &attempts, 3).unwrap());
+        assert_eq!(attempts.failures.get(), 1);
+    }
+
+    #[test]
+    fn cancelled_tries_are_withdrawn() {
+        let f = FakeFramework::new(&[Outcome::Failure, Outcome::Cancelled]);
+        let attempts = FakeAttempts::new(10);
+        let err = try_framework(&f, &attempts, 3).unwrap_err();
+        assert!(matches!(err.downcast_ref::<AuthError>(), Some(AuthError::Cancelled)));
+        assert_eq!(attempts.failures.get(), 1);
+    }
+
+    #[test]
+    fn unusable_frameworks_are_withdrawn() {
+        let f = FakeFramework::new(&[Outcome::Unusable]);
+        let attempts = FakeAttempts::new(10);
+        assert!(try_framework(&f, &attempts, 3).is_err());
+        assert_eq!(attempts.failures.get(), 0);
+    }
+
+    #[test]
+    fn tries_stop_at_lockout() {
+        let f = FakeFramework::new(&[Outcome::Failure, Outcome::Failure, Outcome::Failure]);
+        let attempts = FakeAttempts::new(2);
+        assert!(!try_framework(&f, &attempts, 3).unwrap());
+        assert_eq!(f.tries.get(), 2);
+    }
 }
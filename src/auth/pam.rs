@@ -1,15 +1,13 @@
 use std::ffi::CString;
 use std::error::Error;
-use std::io::Write;
 use std::os::raw::c_char;
 
-extern crate termion;
-use self::termion::input::TermRead;
-use self::termion::get_tty;
-use self::termion::raw::IntoRawMode;
+use zeroize::Zeroize;
+use zeroize::Zeroizing;
 
 use settings::Settings;
 use auth::AuthFramework;
+use auth::input::PasswordInput;
 use osutils::OSUtils;
 
 // C function prototypes
@@ -25,28 +23,40 @@ const PAM_NAME: &'static str = "PAM";
 pub struct PamAuthFramework<'a, T: OSUtils + 'a> {
     osutils: &'a T,
     settings: &'a Settings,
+    auth_user: &'a str, // Account whose password is asked for
+    input: &'a PasswordInput,
 }
 
 impl<'a, T> PamAuthFramework<'a, T> where T: OSUtils {
-    pub fn new(osutils: &'a T, settings: &'a Settings) -> PamAuthFramework<'a, T> {
+    pub fn new(osutils: &'a T, settings: &'a Settings, auth_user: &'a str, input: &'a PasswordInput)
+        -> PamAuthFramework<'a, T> {
         PamAuthFramework {
-            osutils: osutils,
-            settings: settings,
+            osutils,
+            settings,
+            auth_user,
+            input,
         }
     }
 }
 
 impl<'a, T> AuthFramework for PamAuthFramework<'a, T> where T: OSUtils {
     fn authenticate(&self) -> Result<bool, Box<dyn Error>> {
-        // Get the current user's username and convert it to a C string
-        let username = self.osutils.get_username()?;
-        let c_username = CString::new(username)?;
+        // Convert the username of the account to authenticate as to a C string
+        let c_username = CString::new(self.auth_user)?;
 
-        // Prompt the user for a password using the prompt from the settings
-        let password = read_password(self.settings.get_prompt())?;
+        // Prompt the user for a password using the prompt from the settings, unless
+        // it's another account's password that's needed
+        let password = if self.auth_user == self.osutils.get_username()? {
+            self.input.read_password(self.settings.get_prompt())?
+        } else {
+            self.input.read_password(&format!("Password for {}: ", self.auth_user))?
+        };
 
-        // Convert the password into a C String
-        let c_password = CString::new(password)?;
+        // Convert the password into a C String, which is wiped along with the password
+        let c_password = Zeroizing::new(CString::new(password.as_bytes()).map_err(|e| {
+            e.into_vec().zeroize();
+            "Password can't contain NUL characters!"
+        })?);
 
         // Authenticate with the username and password on the C pam bindings
         let res: bool;
@@ -65,21 +75,3 @@ impl<'a, T> AuthFramework for PamAuthFramework<'a, T> where T: OSUtils {
         PAM_NAME
     }
 }
-
-/// Function to read in a password from the controlling TTY
-/// Works even if stdin/stdout are redirected
-fn read_password(prompt: &str) -> Result<String, Box<dyn Error>> {
-    let mut tty = get_tty()?;
-    tty.write_all(prompt.as_bytes())?;
-   
-    // Put the TTY into raw mode so it doesn't echo the user's keystrokes back
-    let res: Result<String, Box<dyn Error>>;
-    {
-        let mut tty_raw = (&mut tty).into_raw_mode()?;
-        res = tty_raw.read_line()?.ok_or_else(|| From::from("Error reading password!"));
-    }
-    
-    // Write a newline and return the result
-    tty.write_all(b"\n")?;
-    res
-}
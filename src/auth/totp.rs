@@ -0,0 +1,284 @@
+//!
+//! Built-in RFC 6238 TOTP authentication
+//!
+
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::error::Error;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+
+extern crate hmac;
+extern crate sha1;
+extern crate base32;
+use self::hmac::{Hmac, Mac};
+use self::sha1::Sha1;
+use self::base32::Alphabet;
+
+use time;
+use serde_json;
+use libc::{flock, LOCK_EX};
+
+use auth::AuthFramework;
+use auth::input::PasswordInput;
+
+use TOTP_SECRETS_PATH;
+
+const TOTP_MAX_TRIES: i32 = 0;
+const TOTP_NAME: &str = "TOTP";
+const TOTP_PROMPT: &str = "Verification code: ";
+
+const TOTP_DIGITS: u32 = 6;
+const TOTP_PERIOD: i64 = 30;
+const TOTP_SKEW_STEPS: u64 = 1; // Number of steps of clock drift tolerated in each direction
+const TOTP_SECRET_LEN: usize = 20;
+
+const BASE32_ALPHABET: Alphabet = Alphabet::RFC4648 { padding: false };
+
+/// A single user's enrolled TOTP secret
+#[derive(Serialize, Deserialize)]
+struct TotpUser {
+    username: String,
+    secret: String,  // Base32-encoded shared secret
+    last_step: u64,  // Last time step a code was accepted for, used to reject replays
+}
+
+/// Contents of the TOTP secrets file
+#[derive(Serialize, Deserialize, Default)]
+struct TotpSecrets {
+    users: Vec<TotpUser>,
+}
+
+/// Open the secrets file and take an exclusive lock on it. The lock is held
+/// until the returned file is dropped, which serializes concurrent verifications
+/// so that a code can't be accepted twice.
+fn open_secrets_locked() -> Result<(File, TotpSecrets), Box<dyn Error>> {
+    let mut f = OpenOptions::new().read(true).write(true).create(true).truncate(false).mode(0o600)
+        .open(TOTP_SECRETS_PATH)?;
+
+    // Refuse to trust the file unless only root can read or modify it
+    let metadata = f.metadata()?;
+    if metadata.uid() != 0 || metadata.mode() & 0o077 != 0 {
+        return Err(From::from(format!("{} must be owned by root with mode 0600!", TOTP_SECRETS_PATH)));
+    }
+
+    if unsafe { flock(f.as_raw_fd(), LOCK_EX) } != 0 {
+        return Err(From::from("Failed to lock TOTP secrets file!"));
+    }
+
+    let mut buf = String::new();
+    f.read_to_string(&mut buf)?;
+    let secrets: TotpSecrets = if buf.trim().is_empty() {
+        TotpSecrets::default()
+    } else {
+        serde_json::from_str(&buf)?
+    };
+
+    Ok((f, secrets))
+}
+
+/// Rewrite the locked secrets file in place
+fn write_secrets(f: &mut File, secrets: &TotpSecrets) -> Result<(), Box<dyn Error>> {
+    let secrets_str = serde_json::to_string_pretty(secrets)?;
+    f.set_len(0)?;
+    f.seek(SeekFrom::Start(0))?;
+    f.write_all(secrets_str.as_bytes())?;
+    f.sync_all()?;
+    Ok(())
+}
+
+/// Compute the HOTP value (RFC 4226) of the given secret and counter with the given number of digits
+fn hotp(secret: &[u8], counter: u64, digits: u32) -> Result<u32, Box<dyn Error>> {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret)
+        .map_err(|_| "Invalid TOTP secret length!")?;
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    // Dynamic truncation
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let code = ((hash[offset] as u32 & 0x7f) << 24)
+        | ((hash[offset + 1] as u32) << 16)
+        | ((hash[offset + 2] as u32) << 8)
+        | (hash[offset + 3] as u32);
+
+    Ok(code % 10u32.pow(digits))
+}
+
+/// Compare two strings without short-circuiting on the first difference
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b.iter()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Find the time step that the given code is valid for, if any.
+/// Only steps after `last_step` are considered so that used codes can't be replayed.
+fn verify_code(secret: &[u8], code: &str, last_step: u64) -> Result<Option<u64>, Box<dyn Error>> {
+    let cur_step = (time::OffsetDateTime::now_utc().unix_timestamp() / TOTP_PERIOD) as u64;
+    verify_code_at(secret, code, last_step, cur_step)
+}
+
+/// Like verify_code, with the current time step given
+fn verify_code_at(secret: &[u8], code: &str, last_step: u64, cur_step: u64) -> Result<Option<u64>, Box<dyn Error>> {
+    for step in cur_step.saturating_sub(TOTP_SKEW_STEPS)..(cur_step + TOTP_SKEW_STEPS + 1) {
+        if step <= last_step {
+            continue;
+        }
+
+        let expected = format!("{:0width$}", hotp(secret, step, TOTP_DIGITS)?, width = TOTP_DIGITS as usize);
+        if constant_time_eq(expected.as_bytes(), code.as_bytes()) {
+            return Ok(Some(step));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Generate a new secret for the given user, store it, and return a provisioning URI.
+/// Existing enrollments are only replaced if `replace` is set.
+pub fn enroll_user(username: &str, hostname: &str, replace: bool) -> Result<String, Box<dyn Error>> {
+    let (mut f, mut secrets) = open_secrets_locked()?;
+
+    let existing = secrets.users.iter().position(|u| u.username == username);
+    if existing.is_some() && !replace {
+        return Err(From::from("A TOTP secret is already enrolled for this user! Ask an administrator to reset it."));
+    }
+
+    // Generate a new secret from the system's CSPRNG
+    let mut secret = [0u8; TOTP_SECRET_LEN];
+    File::open("/dev/urandom")?.read_exact(&mut secret)?;
+    let secret_str = base32::encode(BASE32_ALPHABET, &secret);
+
+    let new_user = TotpUser {
+        username: username.to_string(),
+        secret: secret_str.clone(),
+        last_step: 0,
+    };
+    match existing {
+        Some(i) => secrets.users[i] = new_user,
+        None => secrets.users.push(new_user),
+    }
+    write_secrets(&mut f, &secrets)?;
+
+    Ok(format!("otpauth://totp/rudo:{}@{}?secret={}&issuer=rudo&algorithm=SHA1&digits={}&period={}",
+               username, hostname, secret_str, TOTP_DIGITS, TOTP_PERIOD))
+}
+
+pub struct TotpAuthFramework<'a> {
+    auth_user: &'a str, // Account whose secret the code is checked against
+    input: &'a PasswordInput,
+}
+
+impl<'a> TotpAuthFramework<'a> {
+    pub fn new(auth_user: &'a str, input: &'a PasswordInput) -> TotpAuthFramework<'a> {
+        TotpAuthFramework { auth_user, input }
+    }
+}
+
+impl<'a> AuthFramework for TotpAuthFramework<'a> {
+    fn authenticate(&self) -> Result<bool, Box<dyn Error>> {
+        let username = self.auth_user;
+
+        // Make sure the user is enrolled before prompting for a code
+        {
+            let (_, secrets) = open_secrets_locked()?;
+            if !secrets.users.iter().any(|u| u.username == username) {
+                return Err(From::from("No TOTP secret enrolled for this user! Run rudo --totp-enroll."));
+            }
+        }
+
+        let code = self.input.read_password(TOTP_PROMPT)?;
+        let code = code.trim();
+
+        // Hold the lock across verification and update so concurrent invocations
+        // can't both accept the same code
+        let (mut f, mut secrets) = open_secrets_locked()?;
+        let user = match secrets.users.iter_mut().find(|u| u.username == username) {
+            Some(u) => u,
+            None => return Ok(false),
+        };
+
+        let secret = base32::decode(BASE32_ALPHABET, &user.secret)
+            .ok_or("Corrupted TOTP secret in secrets file!")?;
+
+        match verify_code(&secret, code, user.last_step)? {
+            Some(step) => {
+                user.last_step = step;
+                write_secrets(&mut f, &secrets)?;
+                Ok(true)
+            },
+            None => Ok(false),
+        }
+    }
+
+    fn get_max_tries(&self) -> i32 {
+        TOTP_MAX_TRIES
+    }
+
+    fn get_name(&self) -> &'static str {
+        TOTP_NAME
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Shared secret of the SHA-1 test vectors in RFC 6238, Appendix B
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    fn code_at(step: u64) -> String {
+        format!("{:06}", hotp(RFC_SECRET, step, TOTP_DIGITS).unwrap())
+    }
+
+    #[test]
+    fn rfc6238_sha1_vectors() {
+        let vectors: [(i64, u32); 6] = [
+            (59, 94287082),
+            (1111111109, 7081804),
+            (1111111111, 14050471),
+            (1234567890, 89005924),
+            (2000000000, 69279037),
+            (20000000000, 65353130),
+        ];
+        for &(time, expected) in vectors.iter() {
+            assert_eq!(hotp(RFC_SECRET, (time / TOTP_PERIOD) as u64, 8).unwrap(), expected, "time {}", time);
+        }
+    }
+
+    #[test]
+    fn accepts_codes_within_window() {
+        let cur_step = 1000;
+        for step in [cur_step - TOTP_SKEW_STEPS, cur_step, cur_step + TOTP_SKEW_STEPS].iter() {
+            assert_eq!(verify_code_at(RFC_SECRET, &code_at(*step), 0, cur_step).unwrap(), Some(*step));
+        }
+    }
+
+    #[test]
+    fn rejects_codes_outside_window() {
+        let cur_step = 1000;
+        for step in [cur_step - TOTP_SKEW_STEPS - 1, cur_step + TOTP_SKEW_STEPS + 1].iter() {
+            assert_eq!(verify_code_at(RFC_SECRET, &code_at(*step), 0, cur_step).unwrap(), None);
+        }
+        assert_eq!(verify_code_at(RFC_SECRET, "", 0, cur_step).unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_replayed_codes() {
+        let cur_step = 1000;
+        let code = code_at(cur_step);
+        let step = verify_code_at(RFC_SECRET, &code, 0, cur_step).unwrap().unwrap();
+        assert_eq!(verify_code_at(RFC_SECRET, &code, step, cur_step).unwrap(), None);
+
+        // Codes of steps before the last accepted one are replays too
+        let earlier = code_at(cur_step - 1);
+        assert_eq!(verify_code_at(RFC_SECRET, &earlier, step, cur_step).unwrap(), None);
+        assert_eq!(verify_code_at(RFC_SECRET, &code_at(cur_step + 1), step, cur_step).unwrap(), Some(cur_step + 1));
+    }
+}
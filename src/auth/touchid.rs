@@ -21,19 +21,26 @@ const TOUCHID_NAME: &'static str = "TouchID";
 pub struct TouchIDAuthFramework<'a, T: OSUtils + 'a> {
     osutils: &'a T,
     settings: &'a Settings,
+    auth_user: &'a str,
 }
 
 impl<'a, T> TouchIDAuthFramework<'a, T> where T: OSUtils {
-    pub fn new(osutils: &'a T, settings: &'a Settings) -> TouchIDAuthFramework<'a, T> {
+    pub fn new(osutils: &'a T, settings: &'a Settings, auth_user: &'a str) -> TouchIDAuthFramework<'a, T> {
         TouchIDAuthFramework {
-            osutils: osutils,
-            settings: settings
+            osutils,
+            settings,
+            auth_user
         }
     }
 }
 
 impl<'a, T> AuthFramework for TouchIDAuthFramework<'a, T> where T: OSUtils {
     fn authenticate(&self) -> Result<bool, Box<dyn Error>> {
+        // TouchID can only vouch for whoever is sitting at the machine
+        if self.auth_user != self.osutils.get_username()? {
+            return Err(From::from("TouchID can't authenticate as another user!"));
+        }
+
         // Set effective UID to the caller's UID so we can use TouchID
         let current_uid = self.osutils.get_current_uid()?;
         unsafe { seteuid(current_uid); }
@@ -0,0 +1,207 @@
+//!
+//! Terminal mode handling for password prompts
+//!
+
+use std::io;
+use std::ptr;
+use std::mem::MaybeUninit;
+use std::error::Error;
+use std::time::Instant;
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+
+use libc::{c_int, termios, tcgetattr, tcsetattr, TCSANOW, TCSAFLUSH};
+use libc::{sigaction, sigemptyset, SIG_DFL, SIGINT, SIGTERM, SIGHUP, SIGQUIT, SIGTSTP};
+use libc::{poll, pollfd, POLLIN, EINTR};
+use libc::{ECHO, ECHONL, ICANON, ISIG, VMIN, VTIME};
+
+use auth::AuthError;
+
+// State shared with the signal handlers. TTY_FD is -1 whenever the saved modes aren't valid.
+static TTY_FD: AtomicI32 = AtomicI32::new(-1);
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static mut SAVED_TERMIOS: MaybeUninit<termios> = MaybeUninit::uninit();
+static mut PROMPT_TERMIOS: MaybeUninit<termios> = MaybeUninit::uninit();
+
+/// Install `handler` for `sig`, returning the previous action
+unsafe fn set_handler(sig: c_int, handler: extern "C" fn(c_int)) -> Option<libc::sigaction> {
+    // No SA_RESTART, so a blocking poll() returns EINTR and notices the signal
+    let mut action: libc::sigaction = MaybeUninit::zeroed().assume_init();
+    action.sa_sigaction = handler as usize;
+    sigemptyset(&mut action.sa_mask);
+
+    let mut old_action: libc::sigaction = MaybeUninit::zeroed().assume_init();
+    if sigaction(sig, &action, &mut old_action) == 0 {
+        Some(old_action)
+    } else {
+        None
+    }
+}
+
+/// Restore the saved terminal mode, then terminate the way the signal normally would
+extern "C" fn restore_and_reraise(sig: c_int) {
+    unsafe {
+        let fd = TTY_FD.swap(-1, Ordering::SeqCst);
+        if fd >= 0 {
+            tcsetattr(fd, TCSANOW, (*ptr::addr_of!(SAVED_TERMIOS)).as_ptr());
+            libc::write(fd, b"\n".as_ptr() as *const _, 1);
+        }
+        libc::signal(sig, SIG_DFL);
+        libc::raise(sig);
+    }
+}
+
+/// Ctrl-C cancels the prompt. The reader notices the flag once poll() is interrupted.
+extern "C" fn set_interrupted(_sig: c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Ctrl-Z restores the terminal while rudo is stopped and re-enters the prompt mode
+/// once it's continued
+extern "C" fn restore_and_stop(sig: c_int) {
+    unsafe {
+        let fd = TTY_FD.load(Ordering::SeqCst);
+        if fd >= 0 {
+            tcsetattr(fd, TCSANOW, (*ptr::addr_of!(SAVED_TERMIOS)).as_ptr());
+        }
+
+        libc::signal(sig, SIG_DFL);
+        libc::raise(sig);
+
+        // Execution continues here after SIGCONT
+        set_handler(sig, restore_and_stop);
+        if fd >= 0 {
+            tcsetattr(fd, TCSANOW, (*ptr::addr_of!(PROMPT_TERMIOS)).as_ptr());
+        }
+    }
+}
+
+/// Control characters of the original terminal mode, used for line editing
+/// when the terminal can't do it itself
+pub struct EditChars {
+    pub erase: u8,
+    pub kill: u8,
+    pub werase: u8,
+    pub eof: u8,
+}
+
+/// Puts a TTY into a mode suitable for reading passwords, and restores the original
+/// mode when dropped or when rudo is killed by a signal.
+pub struct TtyGuard {
+    fd: RawFd,
+    old_actions: Vec<(c_int, libc::sigaction)>,
+    edit_chars: EditChars,
+}
+
+impl TtyGuard {
+    /// Turn off echo. With `canonical` set the terminal keeps handling line editing
+    /// (erase, kill, word erase) itself. Otherwise every keystroke is read as it's typed,
+    /// so the caller can give feedback and has to do the editing.
+    pub fn new(fd: RawFd, canonical: bool) -> Result<TtyGuard, Box<dyn Error>> {
+        unsafe {
+            let mut saved = MaybeUninit::<termios>::uninit();
+            if tcgetattr(fd, saved.as_mut_ptr()) != 0 {
+                return Err(Box::new(io::Error::last_os_error()));
+            }
+            let saved = saved.assume_init();
+
+            let mut prompt = saved;
+            prompt.c_lflag &= !(ECHO | ECHONL);
+            prompt.c_lflag |= ISIG;
+            if canonical {
+                prompt.c_lflag |= ICANON;
+            } else {
+                prompt.c_lflag &= !ICANON;
+                prompt.c_cc[VMIN] = 1;
+                prompt.c_cc[VTIME] = 0;
+            }
+
+            *ptr::addr_of_mut!(SAVED_TERMIOS) = MaybeUninit::new(saved);
+            *ptr::addr_of_mut!(PROMPT_TERMIOS) = MaybeUninit::new(prompt);
+            INTERRUPTED.store(false, Ordering::SeqCst);
+            TTY_FD.store(fd, Ordering::SeqCst);
+
+            // Install the handlers before changing the mode so there's no window
+            // where a signal could leave the terminal without echo
+            let mut guard = TtyGuard {
+                fd,
+                old_actions: Vec::new(),
+                edit_chars: EditChars {
+                    erase: saved.c_cc[libc::VERASE],
+                    kill: saved.c_cc[libc::VKILL],
+                    werase: saved.c_cc[libc::VWERASE],
+                    eof: saved.c_cc[libc::VEOF],
+                },
+            };
+            let handlers: [(c_int, extern "C" fn(c_int)); 5] = [
+                (SIGTERM, restore_and_reraise),
+                (SIGHUP, restore_and_reraise),
+                (SIGQUIT, restore_and_reraise),
+                (SIGINT, set_interrupted),
+                (SIGTSTP, restore_and_stop),
+            ];
+            for &(sig, handler) in handlers.iter() {
+                if let Some(old_action) = set_handler(sig, handler) {
+                    guard.old_actions.push((sig, old_action));
+                }
+            }
+
+            if tcsetattr(fd, TCSAFLUSH, &prompt) != 0 {
+                return Err(Box::new(io::Error::last_os_error()));
+            }
+
+            Ok(guard)
+        }
+    }
+
+    pub fn get_edit_chars(&self) -> &EditChars {
+        &self.edit_chars
+    }
+}
+
+impl Drop for TtyGuard {
+    fn drop(&mut self) {
+        unsafe {
+            if TTY_FD.swap(-1, Ordering::SeqCst) >= 0 {
+                tcsetattr(self.fd, TCSANOW, (*ptr::addr_of!(SAVED_TERMIOS)).as_ptr());
+            }
+            for &(sig, ref old_action) in self.old_actions.iter() {
+                sigaction(sig, old_action, ptr::null_mut());
+            }
+        }
+    }
+}
+
+/// Wait until the fd has input to read, failing with `AuthError::Timeout` once the
+/// deadline has passed, or `AuthError::Cancelled` if Ctrl-C was pressed.
+/// No deadline means waiting forever.
+pub fn wait_readable(fd: RawFd, deadline: Option<Instant>) -> Result<(), Box<dyn Error>> {
+    loop {
+        if INTERRUPTED.swap(false, Ordering::SeqCst) {
+            return Err(Box::new(AuthError::Cancelled));
+        }
+
+        let timeout_ms: c_int = match deadline {
+            Some(d) => {
+                let now = Instant::now();
+                if now >= d {
+                    return Err(Box::new(AuthError::Timeout));
+                }
+                let remaining = d - now;
+                remaining.as_millis().min(c_int::MAX as u128) as c_int
+            },
+            None => -1,
+        };
+
+        let mut pfd = pollfd { fd, events: POLLIN, revents: 0 };
+        let res = unsafe { poll(&mut pfd, 1, timeout_ms) };
+        if res > 0 {
+            return Ok(());
+        } else if res < 0 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() != Some(EINTR) {
+                return Err(Box::new(err));
+            }
+        }
+    }
+}
@@ -0,0 +1,201 @@
+//!
+//! rudo-replay: lists and replays the I/O of commands recorded by rudo's `log_io`
+//!
+
+use std::env;
+use std::io;
+use std::thread;
+use std::process;
+use std::fs::File;
+use std::error::Error;
+use std::io::Read;
+use std::io::Write;
+use std::time::Duration;
+
+#[allow(dead_code)] // Shared with rudo, which writes the recordings
+#[path = "../iolog.rs"]
+mod iolog;
+use iolog::Stream;
+use iolog::TimingEvent;
+
+extern crate time;
+extern crate libc;
+
+extern crate getopts;
+use getopts::Options;
+
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+
+// Global config
+pub static IO_LOG_PATH: &'static str = "/var/log/rudo-io";
+
+// Streams that are replayed unless -f is given
+static DEFAULT_STREAMS: [Stream; 3] = [Stream::TtyOut, Stream::Stdout, Stream::Stderr];
+
+fn print_help(program_name: &str, opts: Options) {
+    let brief = format!("Usage: {} [flags] <id>\n       {} -l [user]", program_name, program_name);
+    writeln!(&mut io::stderr(), "{}", opts.usage(&brief))
+        .expect("Failed to write to stderr!");
+}
+
+/// Format a UNIX timestamp for display
+fn format_timestamp(timestamp: i64) -> String {
+    time::OffsetDateTime::from_unix_timestamp(timestamp).format("%F %T UTC")
+}
+
+///
+/// Handles -l: lists the recordings of all users, or the given user
+///
+fn list_recordings(dir: &str, username: Option<String>) -> Result<i32, Box<dyn Error>> {
+    let logs: Vec<_> = iolog::list_logs(dir)?.into_iter()
+        .filter(|l| username.as_ref().is_none_or(|u| *u == l.info.user)).collect();
+    if logs.is_empty() {
+        println!("No recordings.");
+        return Ok(0);
+    }
+
+    println!("{:<24} {:<16} {:<16} {:<24} {:<5} COMMAND", "ID", "USER", "TARGET", "STARTED", "EXIT");
+    for log in logs.iter() {
+        let exit_code = log.info.exit_code.map_or(String::from("-"), |c| c.to_string());
+        let mut command = log.info.command.clone();
+        for arg in log.info.args.iter() {
+            command += " ";
+            command += arg;
+        }
+        println!("{:<24} {:<16} {:<16} {:<24} {:<5} {}", log.id, log.info.user, log.info.target_user,
+                 format_timestamp(log.info.start_timestamp), exit_code, command);
+    }
+    Ok(0)
+}
+
+///
+/// Handles replaying a recording to stdout
+/// @param speed factor to speed up the replay by
+/// @param max_wait longest pause between two events in seconds, if any
+/// @param streams streams to replay
+///
+fn replay(dir: &str, id: &str, speed: f64, max_wait: Option<f64>, streams: &[Stream]) -> Result<i32, Box<dyn Error>> {
+    let log = iolog::open_log(dir, id)?;
+    let events = log.read_timing(dir)?;
+
+    let mut files: Vec<(Stream, Option<File>)> = Vec::new();
+    for stream in iolog::ALL_STREAMS.iter() {
+        files.push((*stream, log.open_stream(dir, *stream)?));
+    }
+
+    writeln!(&mut io::stderr(), "Replaying {} run by {} as {} at {}", log.info.command, log.info.user,
+             log.info.target_user, format_timestamp(log.info.start_timestamp))?;
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut buf = Vec::new();
+    for event in events.iter() {
+        let delay = match *event {
+            TimingEvent::Data(_, delay, _) | TimingEvent::WindowSize(delay, _, _) => delay / speed,
+        };
+        let delay = max_wait.map_or(delay, |m| delay.min(m));
+        if delay > 0.0 {
+            thread::sleep(Duration::from_secs_f64(delay));
+        }
+
+        // The user's terminal can't be resized, so only data is replayed
+        if let TimingEvent::Data(stream, _, len) = *event {
+            let f = match files.iter_mut().find(|&&mut (s, _)| s == stream) {
+                Some(&mut (_, Some(ref mut f))) => f,
+                _ => return Err(From::from(format!("Recording has no {} data!", stream.name()))),
+            };
+
+            // Data of the other streams still has to be read to keep the files in step
+            buf.resize(len, 0);
+            f.read_exact(&mut buf)?;
+            if streams.contains(&stream) {
+                out.write_all(&buf)?;
+                out.flush()?;
+            }
+        }
+    }
+
+    match log.info.exit_code {
+        Some(code) => writeln!(&mut io::stderr(), "\nCommand exited with {}", code)?,
+        None => writeln!(&mut io::stderr(), "\nRecording ended before the command exited")?,
+    }
+    Ok(0)
+}
+
+/// Parse a comma-separated list of stream names
+fn parse_streams(names: &str) -> Result<Vec<Stream>, Box<dyn Error>> {
+    names.split(',').map(|n| Stream::from_name(n.trim())
+                         .ok_or_else(|| From::from(format!("Unknown stream: {}", n)))).collect()
+}
+
+/// Parse a number of seconds or a speed factor, which has to be positive
+fn parse_positive(value: &str) -> Result<f64, Box<dyn Error>> {
+    match value.parse::<f64>() {
+        Ok(v) if v > 0.0 && v.is_finite() => Ok(v),
+        _ => Err(From::from(format!("Expected a positive number, got {}", value))),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let program_name = args[0].clone();
+    let mut opts = Options::new();
+
+    // Set up arguments
+    opts.optflag("h", "help", "print this help menu");
+    opts.optflag("l", "list", "list recordings of all users, or the given user");
+    opts.optopt("d", "directory", &format!("read recordings from the given directory instead of {}", IO_LOG_PATH), "<dir>");
+    opts.optopt("s", "speed", "replay faster (> 1) or slower (< 1) than real time", "<factor>");
+    opts.optopt("m", "max-wait", "never pause longer than the given number of seconds", "<seconds>");
+    opts.optopt("f", "filter", "streams to replay, out of stdin, stdout, stderr, ttyin and ttyout (default ttyout,stdout,stderr)", "<streams>");
+
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => { m }
+        Err(_) => { print_help(&program_name, opts); process::exit(1); }
+    };
+
+    // Handle help
+    if matches.opt_present("h") {
+        print_help(&program_name, opts);
+        process::exit(0);
+    }
+
+    let dir = matches.opt_str("d").unwrap_or_else(|| String::from(IO_LOG_PATH));
+
+    // Handle --list
+    if matches.opt_present("l") {
+        let res = list_recordings(&dir, matches.free.first().cloned()).unwrap_or_else(|e| {
+            writeln!(&mut io::stderr(), "Failed to list recordings: {}", e).unwrap();
+            process::exit(1);
+        });
+        process::exit(res);
+    }
+
+    if matches.free.len() != 1 {
+        print_help(&program_name, opts);
+        process::exit(1);
+    }
+
+    // Parse the replay options
+    let options = (|| -> Result<_, Box<dyn Error>> {
+        let speed = matches.opt_str("s").map_or(Ok(1.0), |s| parse_positive(&s))?;
+        let max_wait = match matches.opt_str("m") {
+            Some(m) => Some(parse_positive(&m)?),
+            None => None,
+        };
+        let streams = matches.opt_str("f").map_or(Ok(DEFAULT_STREAMS.to_vec()), |f| parse_streams(&f))?;
+        Ok((speed, max_wait, streams))
+    })();
+    let (speed, max_wait, streams) = options.unwrap_or_else(|e| {
+        writeln!(&mut io::stderr(), "{}", e).unwrap();
+        process::exit(1);
+    });
+
+    let res = replay(&dir, &matches.free[0], speed, max_wait, &streams).unwrap_or_else(|e| {
+        writeln!(&mut io::stderr(), "Failed to replay recording: {}", e).unwrap();
+        process::exit(1);
+    });
+    process::exit(res);
+}
@@ -0,0 +1,231 @@
+//!
+//! Editing files as another user without running the editor as them, like sudoedit
+//!
+//! The files are copied to temporary files owned by the invoking user, who edits them with
+//! their own editor and privileges, so the editor can't be used to run anything as the
+//! target user. The changes are then written back with the target user's permissions.
+//!
+
+use std::io;
+use std::fs;
+use std::env;
+use std::ptr;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::error::Error;
+use std::ffi::CString;
+use std::io::Read;
+use std::io::Write;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+use std::os::unix::io::FromRawFd;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::fs::OpenOptionsExt;
+
+use libc::{gid_t, uid_t, access, getgroups, setgroups, getegid, setegid, seteuid, mkstemps, W_OK};
+
+use EDIT_TMP_PATH;
+use DEFAULT_EDITOR;
+
+/// Environment variables that name the user's editor, in order of preference
+static EDITOR_VARS: [&str; 3] = ["RUDO_EDITOR", "VISUAL", "EDITOR"];
+
+/// Switches rudo's effective ids to another user, and back when dropped, so files are
+/// accessed with that user's permissions instead of root's
+struct EffectiveUser {
+    egid: gid_t,
+    groups: Vec<gid_t>,
+}
+
+impl EffectiveUser {
+    fn new(uid: uid_t, gid: gid_t) -> Result<EffectiveUser, Box<dyn Error>> {
+        let mut groups = vec![0; unsafe { getgroups(0, ptr::null_mut()) }.max(0) as usize];
+        let count = unsafe { getgroups(groups.len() as libc::c_int, groups.as_mut_ptr()) };
+        if count < 0 {
+            return Err(Box::new(io::Error::last_os_error()));
+        }
+        groups.truncate(count as usize);
+
+        let user = EffectiveUser { egid: unsafe { getegid() }, groups };
+        unsafe {
+            if setgroups(1, &gid) != 0 || setegid(gid) != 0 || seteuid(uid) != 0 {
+                return Err(Box::new(io::Error::last_os_error()));
+            }
+        }
+        Ok(user)
+    }
+}
+
+impl Drop for EffectiveUser {
+    fn drop(&mut self) {
+        unsafe {
+            seteuid(0);
+            setegid(self.egid);
+            setgroups(self.groups.len() as _, self.groups.as_ptr());
+        }
+    }
+}
+
+/// A file being edited
+pub struct EditFile {
+    pub path: String,          // Absolute path of the file
+    pub tmp_path: String,      // Copy that the user edits
+    original: Option<Vec<u8>>, // Contents before editing, or None if the file doesn't exist yet
+}
+
+impl EditFile {
+    /// Remove the temporary copy
+    pub fn discard(&self) {
+        let _ = fs::remove_file(&self.tmp_path);
+    }
+}
+
+/// Make path absolute and remove `.` and `..` from it. Since no part of an edited path may be
+/// a symlink, this names the same file as the path itself.
+pub fn resolve_path(path: &str) -> Result<String, Box<dyn Error>> {
+    let mut resolved = PathBuf::from("/");
+    for component in env::current_dir()?.join(path).components() {
+        match component {
+            Component::Normal(name) => resolved.push(name),
+            Component::ParentDir => { resolved.pop(); },
+            _ => {},
+        }
+    }
+    if resolved.parent().is_none() {
+        return Err(From::from(format!("{} is not a file", path)));
+    }
+    Ok(resolved.to_string_lossy().into_owned())
+}
+
+/// Check that no part of path is a symlink and that it names a regular file, if anything.
+/// If check_writable is set, none of the directories leading to it may be writable by the
+/// invoking user either, since they could swap the file for another one.
+pub fn check_path(path: &str, check_writable: bool) -> Result<(), Box<dyn Error>> {
+    let path = Path::new(path);
+    let mut dirs: Vec<&Path> = path.ancestors().skip(1).collect();
+    dirs.reverse();
+
+    for dir in dirs {
+        let metadata = fs::symlink_metadata(dir)?;
+        if metadata.file_type().is_symlink() {
+            return Err(From::from(format!("{} is a symbolic link", dir.display())));
+        }
+        if !metadata.is_dir() {
+            return Err(From::from(format!("{} is not a directory", dir.display())));
+        }
+
+        // access() checks the real uid, which is the invoking user's
+        let c_dir = CString::new(dir.to_string_lossy().into_owned())?;
+        if check_writable && unsafe { access(c_dir.as_ptr(), W_OK) } == 0 {
+            return Err(From::from(format!("{} is writable by you, so files in it can't be edited", dir.display())));
+        }
+    }
+
+    match fs::symlink_metadata(path) {
+        Ok(ref m) if m.file_type().is_symlink() => {
+            Err(From::from(format!("{} is a symbolic link", path.display())))
+        },
+        Ok(ref m) if !m.is_file() => Err(From::from(format!("{} is not a regular file", path.display()))),
+        Ok(_) => Ok(()),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(Box::new(e)),
+    }
+}
+
+/// Open a regular file without following symlinks. The file is opened without blocking,
+/// so a FIFO swapped in for it is rejected instead of waiting for the other end.
+fn open_regular(path: &str, options: &mut OpenOptions) -> Result<File, Box<dyn Error>> {
+    let f = options.custom_flags(libc::O_NOFOLLOW | libc::O_NONBLOCK).open(path)?;
+    if !f.metadata()?.is_file() {
+        return Err(From::from(format!("{} is not a regular file", path)));
+    }
+    Ok(f)
+}
+
+/// Copy the file at path, read as the target user, to a new temporary file owned by the invoker
+pub fn start_edit(path: &str, target: (u32, u32), invoker: (u32, u32)) -> Result<EditFile, Box<dyn Error>> {
+    let original = {
+        let _user = EffectiveUser::new(target.0, target.1)?;
+        match open_regular(path, OpenOptions::new().read(true)) {
+            Ok(mut f) => {
+                let mut buf = Vec::new();
+                f.read_to_end(&mut buf)?;
+                Some(buf)
+            },
+            Err(e) => match e.downcast_ref::<io::Error>() {
+                Some(e) if e.kind() == io::ErrorKind::NotFound => None,
+                _ => return Err(e),
+            },
+        }
+    };
+
+    // Keep the name and extension in the copy, so editors recognize the type of file
+    let file_path = Path::new(path);
+    let stem = file_path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let ext = file_path.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    let template = CString::new(format!("{}/{}.XXXXXX{}", EDIT_TMP_PATH, stem, ext))?;
+
+    let _user = EffectiveUser::new(invoker.0, invoker.1)?;
+    let mut template = template.into_bytes_with_nul();
+    let fd = unsafe { mkstemps(template.as_mut_ptr() as *mut libc::c_char, ext.len() as libc::c_int) };
+    if fd < 0 {
+        return Err(Box::new(io::Error::last_os_error()));
+    }
+    template.pop();
+    let file = EditFile {
+        path: path.to_string(),
+        tmp_path: String::from_utf8(template)?,
+        original,
+    };
+
+    let mut f = unsafe { File::from_raw_fd(fd) };
+    if let Err(e) = f.write_all(file.original.as_deref().unwrap_or_default()) {
+        file.discard();
+        return Err(Box::new(e));
+    }
+    Ok(file)
+}
+
+/// Write the edited copy back to the file as the target user, unless it's unchanged.
+/// Returns whether the file was changed. The copy is left in place if it couldn't be written.
+pub fn finish_edit(file: &EditFile, target: (u32, u32), invoker: (u32, u32)) -> Result<bool, Box<dyn Error>> {
+    let mut contents = Vec::new();
+    {
+        let _user = EffectiveUser::new(invoker.0, invoker.1)?;
+        let mut f = open_regular(&file.tmp_path, OpenOptions::new().read(true))?;
+        if f.metadata()?.uid() != invoker.0 {
+            return Err(From::from(format!("{} was replaced", file.tmp_path)));
+        }
+        f.read_to_end(&mut contents)?;
+    }
+
+    // Unchanged files aren't written, and neither are new files that were left empty
+    if file.original.as_deref().unwrap_or_default() == contents.as_slice() {
+        file.discard();
+        return Ok(false);
+    }
+
+    // The path was checked before editing, but check it again in case it changed since
+    check_path(&file.path, invoker.0 != 0)?;
+    {
+        let _user = EffectiveUser::new(target.0, target.1)?;
+        // Only truncated once it's known to be a regular file. It's written in place, so it
+        // keeps its owner, mode and links.
+        let mut f = open_regular(&file.path, OpenOptions::new().write(true).create(true).truncate(false).mode(0o644))?;
+        f.set_len(0)?;
+        f.write_all(&contents)?;
+        f.sync_all()?;
+    }
+    file.discard();
+    Ok(true)
+}
+
+/// Get the user's editor and its arguments
+pub fn get_editor() -> Vec<String> {
+    EDITOR_VARS.iter()
+        .filter_map(|var| env::var(var).ok())
+        .map(|editor| editor.split_whitespace().map(String::from).collect::<Vec<_>>())
+        .find(|editor| !editor.is_empty())
+        .unwrap_or_else(|| vec![String::from(DEFAULT_EDITOR)])
+}
@@ -0,0 +1,165 @@
+//!
+//! Hooks: programs that are notified of events, e.g. to send alerts
+//!
+//! Hooks run in a separate process that rudo doesn't wait for, so a slow or failing hook
+//! never delays or changes the outcome of an invocation.
+//!
+
+use std::io;
+use std::thread;
+use std::fs::File;
+use std::error::Error;
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use serde_json;
+use libc::{fork, setsid, setuid, setgid, waitpid, dup2, kill, _exit, SIGKILL};
+
+use settings::Settings;
+use settings::Hook;
+use settings::HookEvent;
+use osutils::OSUtils;
+use osutils::unix::UnixOSUtils;
+
+// How often running hooks are checked on
+const HOOK_POLL_INTERVAL_MS: u64 = 50;
+
+/// What a hook gets on its stdin: the name of the event and its details
+#[derive(Serialize)]
+struct HookPayload<'a, T: Serialize> {
+    event: HookEvent,
+    #[serde(flatten)]
+    details: &'a T,
+}
+
+/// Whether the hook wants to hear about the event, for a command of a rule with the given tag
+fn wants(hook: &Hook, event: HookEvent, tag: Option<&str>) -> bool {
+    hook.events.contains(&event)
+        && (hook.tags.is_empty() || tag.is_some_and(|t| hook.tags.iter().any(|h| h == t)))
+}
+
+/// Start a hook as its unprivileged user, with a clean environment and nothing but the event
+/// on stdin. Its output is discarded.
+fn spawn_hook(hook: &Hook, event_name: &str, payload: &[u8]) -> Result<Child, Box<dyn Error>> {
+    let (uid, gid) = UnixOSUtils::new().get_uidgid_by_username(&hook.user)?;
+    if uid == 0 {
+        return Err(From::from("Hooks must not run as root!"));
+    }
+
+    let mut child = Command::new(&hook.command)
+        .args(&hook.args)
+        .env_clear()
+        .env("PATH", "/usr/bin:/bin")
+        .env("RUDO_EVENT", event_name)
+        .current_dir("/")
+        .uid(uid)
+        .gid(gid)
+        .process_group(0)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+
+    // The event includes the command's arguments, which may not fit in the pipe, so it's
+    // written from a thread. Hooks that don't read it still run and time out.
+    if let Some(mut stdin) = child.stdin.take() {
+        let payload = payload.to_vec();
+        thread::spawn(move || {
+            let _ = stdin.write_all(&payload);
+        });
+    }
+    Ok(child)
+}
+
+/// Run the hooks and wait for them, killing any that are still running after their timeout
+fn supervise(hooks: &[&Hook], event_name: &str, payload: &[u8]) {
+    let start = Instant::now();
+    let mut running: Vec<(Child, Duration)> = hooks.iter().filter_map(|hook| {
+        spawn_hook(hook, event_name, payload).ok().map(|c| (c, Duration::from_secs(hook.timeout_sec)))
+    }).collect();
+
+    while !running.is_empty() {
+        running.retain_mut(|&mut (ref mut child, timeout)| {
+            match child.try_wait() {
+                Ok(None) if start.elapsed() < timeout => true,
+                Ok(None) => {
+                    // Kill the hook along with anything it started
+                    unsafe { kill(-(child.id() as i32), SIGKILL); }
+                    let _ = child.wait();
+                    false
+                },
+                _ => false,
+            }
+        });
+        thread::sleep(Duration::from_millis(HOOK_POLL_INTERVAL_MS));
+    }
+}
+
+/// Close every fd from `first` on
+#[cfg(target_os = "linux")]
+fn close_from(first: u32) {
+    unsafe { libc::close_range(first, u32::MAX, 0); }
+}
+
+/// Close every fd from `first` on
+#[cfg(not(target_os = "linux"))]
+fn close_from(first: u32) {
+    let max = unsafe { libc::sysconf(libc::_SC_OPEN_MAX) };
+    for fd in first as libc::c_long..max {
+        unsafe { libc::close(fd as libc::c_int); }
+    }
+}
+
+/// Run the hooks for an event in the background. Errors are ignored, since hooks
+/// must never affect whether a command runs.
+pub fn run_hooks<T: Serialize>(settings: &Settings, event: HookEvent, tag: Option<&str>, details: &T) {
+    let hooks: Vec<&Hook> = settings.hooks.iter().filter(|h| wants(h, event, tag)).collect();
+    if hooks.is_empty() {
+        return;
+    }
+    let mut payload = match serde_json::to_vec(&HookPayload { event, details }) {
+        Ok(v) => v,
+        Err(_) => return,
+    };
+    payload.push(b'\n');
+    let event_name = serde_json::to_value(event).ok()
+        .and_then(|v| v.as_str().map(String::from)).unwrap_or_default();
+
+    // Fork twice, so the hooks are supervised by a process that rudo doesn't have to wait
+    // for and that doesn't become a zombie once it's done
+    let _ = io::stdout().flush();
+    let _ = io::stderr().flush();
+    let pid = unsafe { fork() };
+    if pid == 0 {
+        unsafe {
+            if fork() != 0 {
+                _exit(0);
+            }
+
+            // Leave the user's session and terminal, so the supervisor isn't hit by
+            // signals meant for rudo and doesn't keep the terminal open
+            setsid();
+            // Drop the invoking user's real ids, so they can't stop or kill the supervisor
+            // to keep an alert from being sent or a hook from being timed out. With an
+            // effective uid of 0 these set the real and saved ids as well.
+            setgid(0);
+            setuid(0);
+            if let Ok(null) = File::options().read(true).write(true).open("/dev/null") {
+                for fd in 0..3 {
+                    dup2(null.as_raw_fd(), fd);
+                }
+            }
+            // Don't hold on to rudo's files, e.g. the lock on the failure record
+            close_from(3);
+        }
+        supervise(&hooks, &event_name, &payload);
+        unsafe { _exit(0); }
+    } else if pid > 0 {
+        let mut status = 0;
+        unsafe { waitpid(pid, &mut status, 0); }
+    }
+}
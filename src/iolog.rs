@@ -0,0 +1,265 @@
+//!
+//! Recordings of the I/O of commands, shared by rudo and rudo-replay
+//!
+//! Every recording is a directory under IO_LOG_PATH named by its id. It holds `log.json`
+//! describing the command, a file with the raw data of every recorded stream and a `timing`
+//! file with one line per event: `<stream> <seconds since the previous event> <bytes>`,
+//! or `winsize <seconds since the previous event> <rows> <cols>` when the terminal is resized.
+//!
+
+use std::io;
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::fs::DirBuilder;
+use std::error::Error;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process;
+use std::time::Instant;
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::fs::OpenOptionsExt;
+
+use serde_json;
+use time;
+
+static INFO_FILE: &str = "log.json";
+static TIMING_FILE: &str = "timing";
+static WINSIZE_EVENT: &str = "winsize";
+
+/// A recorded stream. Streams connected to a terminal are recorded as `TtyIn` and `TtyOut`,
+/// the others under their own name.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Stream {
+    Stdin,
+    Stdout,
+    Stderr,
+    TtyIn,
+    TtyOut,
+}
+
+pub static ALL_STREAMS: [Stream; 5] = [Stream::Stdin, Stream::Stdout, Stream::Stderr, Stream::TtyIn, Stream::TtyOut];
+
+impl Stream {
+    /// Name of the stream in the timing file, which is also the name of its data file
+    pub fn name(self) -> &'static str {
+        match self {
+            Stream::Stdin => "stdin",
+            Stream::Stdout => "stdout",
+            Stream::Stderr => "stderr",
+            Stream::TtyIn => "ttyin",
+            Stream::TtyOut => "ttyout",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Stream> {
+        ALL_STREAMS.iter().find(|s| s.name() == name).cloned()
+    }
+}
+
+/// What was run, kept in `log.json`
+#[derive(Serialize, Deserialize, Clone)]
+pub struct IoLogInfo {
+    pub user: String,              // User that ran rudo
+    pub target_user: String,       // User the command ran as
+    pub command: String,           // Path of the command
+    pub args: Vec<String>,
+    pub cwd: String,
+    pub tty: Option<String>,       // The user's terminal, if there was one
+    pub start_timestamp: i64,
+    pub rows: u16,                 // Size of the terminal when the command started
+    pub cols: u16,
+    #[serde(default)]
+    pub exit_code: Option<i32>,    // Set once the command is done
+}
+
+/// A single line of the timing file
+pub enum TimingEvent {
+    Data(Stream, f64, usize),     // Bytes written to a stream
+    WindowSize(f64, u16, u16),    // New rows and columns of the terminal
+}
+
+/// A recording found under the log directory
+pub struct IoLog {
+    pub id: String,
+    pub info: IoLogInfo,
+}
+
+/// Make sure the directory at path is owned by root and only accessible by root,
+/// creating it if it doesn't exist
+fn init_log_dir(path: &Path) -> Result<(), Box<dyn Error>> {
+    match DirBuilder::new().mode(0o700).create(path) {
+        Ok(_) => {},
+        Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {},
+        Err(e) => return Err(Box::new(e)),
+    }
+
+    let metadata = fs::symlink_metadata(path)?;
+    if !metadata.file_type().is_dir() || metadata.uid() != 0 || metadata.mode() & 0o077 != 0 {
+        return Err(From::from(format!("{} must be a directory only accessible by root!", path.display())));
+    }
+    Ok(())
+}
+
+/// Create a file that only root can read, failing if anything already exists at path
+fn create_log_file(path: &Path) -> Result<File, Box<dyn Error>> {
+    Ok(OpenOptions::new().write(true).create_new(true).mode(0o600)
+        .custom_flags(libc::O_NOFOLLOW).open(path)?)
+}
+
+/// Writes a new recording
+pub struct IoLogWriter {
+    dir: PathBuf,
+    info: IoLogInfo,
+    timing: File,
+    streams: Vec<(Stream, File)>, // Data files of the streams that were written to so far
+    last_event: Instant,
+}
+
+impl IoLogWriter {
+    /// Start a new recording under root, named by the current time and rudo's pid
+    pub fn create(root: &str, info: IoLogInfo) -> Result<IoLogWriter, Box<dyn Error>> {
+        init_log_dir(Path::new(root))?;
+
+        let id = format!("{}-{}", time::OffsetDateTime::from_unix_timestamp(info.start_timestamp)
+                         .format("%Y%m%d-%H%M%S"), process::id());
+        let dir = Path::new(root).join(&id);
+        DirBuilder::new().mode(0o700).create(&dir)?;
+
+        let timing = create_log_file(&dir.join(TIMING_FILE))?;
+        let writer = IoLogWriter { dir, info, timing, streams: Vec::new(), last_event: Instant::now() };
+        writer.write_info()?;
+        Ok(writer)
+    }
+
+    /// Write log.json, replacing it if it exists
+    fn write_info(&self) -> Result<(), Box<dyn Error>> {
+        let tmp_path = self.dir.join(format!("{}.tmp", INFO_FILE));
+        let mut f = create_log_file(&tmp_path)?;
+        f.write_all(serde_json::to_string_pretty(&self.info)?.as_bytes())?;
+        f.sync_all()?;
+        fs::rename(&tmp_path, self.dir.join(INFO_FILE))?;
+        Ok(())
+    }
+
+    /// Seconds since the previous event
+    fn next_delay(&mut self) -> f64 {
+        let now = Instant::now();
+        let delay = now.duration_since(self.last_event).as_secs_f64();
+        self.last_event = now;
+        delay
+    }
+
+    /// Record data that was passed through one of the streams
+    pub fn write(&mut self, stream: Stream, data: &[u8]) -> Result<(), Box<dyn Error>> {
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        if !self.streams.iter().any(|&(s, _)| s == stream) {
+            let f = create_log_file(&self.dir.join(stream.name()))?;
+            self.streams.push((stream, f));
+        }
+        let f = &mut self.streams.iter_mut().find(|&&mut (s, _)| s == stream).unwrap().1;
+        f.write_all(data)?;
+
+        let delay = self.next_delay();
+        writeln!(self.timing, "{} {:.6} {}", stream.name(), delay, data.len())?;
+        Ok(())
+    }
+
+    /// Record a change of the terminal's size
+    pub fn window_changed(&mut self, rows: u16, cols: u16) -> Result<(), Box<dyn Error>> {
+        let delay = self.next_delay();
+        writeln!(self.timing, "{} {:.6} {} {}", WINSIZE_EVENT, delay, rows, cols)?;
+        Ok(())
+    }
+
+    /// Record the command's exit code and flush everything to disk
+    pub fn finish(&mut self, exit_code: i32) -> Result<(), Box<dyn Error>> {
+        for (_, f) in self.streams.iter() {
+            f.sync_all()?;
+        }
+        self.timing.sync_all()?;
+        self.info.exit_code = Some(exit_code);
+        self.write_info()
+    }
+}
+
+/// Whether id could name a recording. Keeps ids from escaping the log directory.
+fn is_valid_id(id: &str) -> bool {
+    !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+/// List the recordings under root, oldest first
+pub fn list_logs(root: &str) -> Result<Vec<IoLog>, Box<dyn Error>> {
+    if !Path::new(root).exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut logs = Vec::new();
+    for entry in fs::read_dir(root)? {
+        let id = entry?.file_name().to_string_lossy().into_owned();
+        if !is_valid_id(&id) {
+            continue;
+        }
+        // Skip directories that don't hold a readable recording
+        if let Ok(log) = open_log(root, &id) {
+            logs.push(log);
+        }
+    }
+    logs.sort_by(|a, b| (a.info.start_timestamp, &a.id).cmp(&(b.info.start_timestamp, &b.id)));
+    Ok(logs)
+}
+
+/// Read the description of the recording with the given id
+pub fn open_log(root: &str, id: &str) -> Result<IoLog, Box<dyn Error>> {
+    if !is_valid_id(id) {
+        return Err(From::from(format!("Invalid recording id: {}", id)));
+    }
+
+    let mut buf = String::new();
+    File::open(Path::new(root).join(id).join(INFO_FILE))
+        .map_err(|_| format!("No recording with id {}!", id))?
+        .read_to_string(&mut buf)?;
+    Ok(IoLog { id: id.to_string(), info: serde_json::from_str(&buf)? })
+}
+
+impl IoLog {
+    /// Read the events from the timing file
+    pub fn read_timing(&self, root: &str) -> Result<Vec<TimingEvent>, Box<dyn Error>> {
+        let timing = fs::read_to_string(Path::new(root).join(&self.id).join(TIMING_FILE))?;
+        let mut events = Vec::new();
+
+        for line in timing.lines() {
+            let fields: Vec<&str> = line.split(' ').collect();
+            let invalid = || format!("Invalid line in timing file: {}", line);
+            match fields.as_slice() {
+                [event, delay, rows, cols] if *event == WINSIZE_EVENT => {
+                    events.push(TimingEvent::WindowSize(delay.parse().map_err(|_| invalid())?,
+                                                        rows.parse().map_err(|_| invalid())?,
+                                                        cols.parse().map_err(|_| invalid())?));
+                },
+                [stream, delay, len] => {
+                    let stream = Stream::from_name(stream).ok_or_else(invalid)?;
+                    events.push(TimingEvent::Data(stream, delay.parse().map_err(|_| invalid())?,
+                                                  len.parse().map_err(|_| invalid())?));
+                },
+                _ => return Err(From::from(invalid())),
+            }
+        }
+        Ok(events)
+    }
+
+    /// Open the data file of a stream, or return None if nothing was written to it
+    pub fn open_stream(&self, root: &str, stream: Stream) -> Result<Option<File>, Box<dyn Error>> {
+        match File::open(Path::new(root).join(&self.id).join(stream.name())) {
+            Ok(f) => Ok(Some(f)),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+}
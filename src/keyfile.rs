@@ -0,0 +1,74 @@
+//!
+//! Secret keys kept in root-only files, used to authenticate records that users can't forge
+//!
+
+use std::io;
+use std::fs;
+use std::process;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::error::Error;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::fs::OpenOptionsExt;
+
+use zeroize::Zeroizing;
+
+// Length of keys in bytes
+const KEY_LEN: usize = 32;
+
+/// Read the key at path. It must be owned by root and not accessible by anyone else.
+pub fn read_key(path: &str) -> Result<Zeroizing<Vec<u8>>, Box<dyn Error>> {
+    let mut f = OpenOptions::new().read(true).custom_flags(libc::O_NOFOLLOW).open(path)?;
+    let metadata = f.metadata()?;
+    if metadata.uid() != 0 || metadata.mode() & 0o077 != 0 {
+        return Err(From::from(format!("{} must be owned by root and not accessible by others!", path)));
+    }
+
+    // Read one byte more than a key into a fixed buffer, so a longer file is caught
+    // without reallocating and leaving copies of the key behind
+    let mut buf = Zeroizing::new([0u8; KEY_LEN + 1]);
+    let mut len = 0;
+    while len < buf.len() {
+        match f.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(Box::new(e)),
+        }
+    }
+    if len != KEY_LEN {
+        return Err(From::from(format!("{} has an invalid length!", path)));
+    }
+
+    let mut key = Zeroizing::new(Vec::with_capacity(KEY_LEN));
+    key.extend_from_slice(&buf[..KEY_LEN]);
+    Ok(key)
+}
+
+/// Read the key at path, generating it first if it doesn't exist
+pub fn get_or_create_key(path: &str) -> Result<Zeroizing<Vec<u8>>, Box<dyn Error>> {
+    if !Path::new(path).exists() {
+        let mut key = Zeroizing::new(vec![0u8; KEY_LEN]);
+        File::open("/dev/urandom")?.read_exact(&mut key)?;
+
+        // Write the key to a temporary file and link it into place, so concurrent
+        // invocations never see a partially written key
+        let tmp_path = format!("{}.{}", path, process::id());
+        let mut f = OpenOptions::new().write(true).create_new(true).mode(0o600)
+            .custom_flags(libc::O_NOFOLLOW).open(&tmp_path)?;
+        f.write_all(&key)?;
+        f.sync_all()?;
+        let res = fs::hard_link(&tmp_path, path);
+        fs::remove_file(&tmp_path)?;
+        match res {
+            Ok(_) => {},
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {},
+            Err(e) => return Err(Box::new(e)),
+        }
+    }
+
+    read_key(path)
+}
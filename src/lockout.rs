@@ -0,0 +1,220 @@
+//!
+//! Persistent tracking of failed authentication attempts and account lockout
+//!
+
+use std::io;
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::fs::DirBuilder;
+use std::error::Error;
+use std::ffi::CString;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::path::Path;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::io::AsRawFd;
+
+use time;
+use serde_json;
+use libc::{flock, LOCK_EX, openlog, syslog, closelog, LOG_AUTHPRIV, LOG_WARNING, LOG_PID};
+
+use settings::Settings;
+use settings::HookEvent;
+use hooks::run_hooks;
+
+use FAILURES_PATH;
+
+/// Failed attempts of a single user since their last successful authentication
+#[derive(Serialize, Deserialize, Default)]
+struct FailureRecord {
+    failures: u32,      // Failed attempts since the last lockout
+    lockouts: u32,      // Consecutive lockouts, used to grow the lockout interval
+    locked_until: i64,  // UNIX Timestamp that the current lockout ends at
+}
+
+/// Details of a lockout passed to hooks
+#[derive(Serialize)]
+struct LockoutDetails {
+    timestamp: String,
+    user: String,
+    failures: u32,    // Failed attempts that caused the lockout
+    lockout_sec: i64, // Length of the lockout
+}
+
+/// Create the failure state directory if it doesn't exist and make sure only root can access it
+fn init_failures_dir() -> Result<(), Box<dyn Error>> {
+    let path = Path::new(FAILURES_PATH);
+    if !path.exists() {
+        DirBuilder::new().mode(0o700).create(path)?;
+    }
+
+    let metadata = fs::symlink_metadata(path)?;
+    if !metadata.is_dir() || metadata.uid() != 0 || metadata.mode() & 0o077 != 0 {
+        return Err(From::from(format!("{} must be a directory owned by root with mode 0700!", FAILURES_PATH)));
+    }
+    Ok(())
+}
+
+/// Open and lock the given user's failure record. The lock is held until the file is dropped.
+fn open_record_locked(username: &str) -> Result<(File, FailureRecord), Box<dyn Error>> {
+    if username.is_empty() || username.contains('/') || username.starts_with('.') {
+        return Err(From::from("Invalid username!"));
+    }
+    init_failures_dir()?;
+
+    let path = format!("{}/{}", FAILURES_PATH, username);
+    let mut f = OpenOptions::new().read(true).write(true).create(true).truncate(false).mode(0o600)
+        .custom_flags(libc::O_NOFOLLOW).open(&path)?;
+
+    if unsafe { flock(f.as_raw_fd(), LOCK_EX) } != 0 {
+        return Err(From::from("Failed to lock failure record!"));
+    }
+
+    let mut buf = String::new();
+    f.read_to_string(&mut buf)?;
+    let record: FailureRecord = if buf.trim().is_empty() {
+        FailureRecord::default()
+    } else {
+        serde_json::from_str(&buf)?
+    };
+
+    Ok((f, record))
+}
+
+/// Rewrite the locked failure record in place
+fn write_record(f: &mut File, record: &FailureRecord) -> Result<(), Box<dyn Error>> {
+    let record_str = serde_json::to_string(record)?;
+    f.set_len(0)?;
+    f.seek(SeekFrom::Start(0))?;
+    f.write_all(record_str.as_bytes())?;
+    Ok(())
+}
+
+/// Send a message to the system log's authpriv facility
+fn log_auth_warning(msg: &str) {
+    let ident = CString::new("rudo").unwrap();
+    let fmt = CString::new("%s").unwrap();
+    let msg = match CString::new(msg) {
+        Ok(v) => v,
+        Err(_) => return,
+    };
+    unsafe {
+        openlog(ident.as_ptr(), LOG_PID, LOG_AUTHPRIV);
+        syslog(LOG_WARNING, fmt.as_ptr(), msg.as_ptr());
+        closelog();
+    }
+}
+
+/// Returns the number of seconds the user remains locked out for, or 0 if they aren't
+pub fn get_lockout_remaining(username: &str, settings: &Settings) -> Result<i64, Box<dyn Error>> {
+    if settings.lockout.max_failures == 0 {
+        return Ok(0);
+    }
+
+    let (_, record) = open_record_locked(username)?;
+    let cur_timestamp = time::OffsetDateTime::now_utc().unix_timestamp();
+    Ok(if record.locked_until > cur_timestamp { record.locked_until - cur_timestamp } else { 0 })
+}
+
+/// Lock the user out, each consecutive lockout doubling in length up to the configured maximum
+fn lock_out(username: &str, settings: &Settings, record: &mut FailureRecord) -> Result<(), Box<dyn Error>> {
+    let lockout = &settings.lockout;
+    let multiplier = 1i64.checked_shl(record.lockouts).filter(|m| *m > 0).unwrap_or(i64::MAX);
+    let duration = lockout.lockout_sec.saturating_mul(multiplier).min(lockout.max_lockout_sec);
+    let cur_timestamp = time::OffsetDateTime::now_utc().unix_timestamp();
+
+    record.failures = 0;
+    record.lockouts = record.lockouts.saturating_add(1);
+    record.locked_until = cur_timestamp.saturating_add(duration);
+
+    log_auth_warning(&format!("user {} locked out for {} seconds after {} failed attempts",
+                              username, duration, lockout.max_failures));
+    let details = LockoutDetails {
+        timestamp: time::OffsetDateTime::from_unix_timestamp(cur_timestamp).format("%FT%TZ"),
+        user: username.to_string(),
+        failures: lockout.max_failures,
+        lockout_sec: duration,
+    };
+    run_hooks(settings, HookEvent::Lockout, None, &details);
+    writeln!(&mut io::stderr(), "Too many failed attempts. Try again in {} seconds.", duration)?;
+    Ok(())
+}
+
+/// Count an attempt against the user before it is made, so that killing rudo while the
+/// credentials are checked doesn't keep it from counting. Attempts that succeed are withdrawn
+/// again with withdraw_attempt. Returns whether the user is locked out instead, which happens
+/// if the attempt that reached the threshold was interrupted before it could lock them out.
+pub fn record_attempt(username: &str, settings: &Settings) -> Result<bool, Box<dyn Error>> {
+    if settings.lockout.max_failures == 0 {
+        return Ok(false);
+    }
+
+    let (mut f, mut record) = open_record_locked(username)?;
+    let locked = record.failures >= settings.lockout.max_failures;
+    if locked {
+        lock_out(username, settings, &mut record)?;
+    } else {
+        record.failures += 1;
+    }
+
+    write_record(&mut f, &record)?;
+    Ok(locked)
+}
+
+/// Withdraw an attempt recorded by record_attempt after it succeeded
+pub fn withdraw_attempt(username: &str, settings: &Settings) -> Result<(), Box<dyn Error>> {
+    if settings.lockout.max_failures == 0 {
+        return Ok(());
+    }
+
+    let (mut f, mut record) = open_record_locked(username)?;
+    if record.failures != 0 {
+        record.failures -= 1;
+        write_record(&mut f, &record)?;
+    }
+    Ok(())
+}
+
+/// Handle a failed attempt that was recorded by record_attempt, locking the user out once they
+/// reach the threshold. Returns whether the user is now locked out.
+pub fn record_failure(username: &str, settings: &Settings) -> Result<bool, Box<dyn Error>> {
+    if settings.lockout.max_failures == 0 {
+        return Ok(false);
+    }
+
+    let (mut f, mut record) = open_record_locked(username)?;
+    if record.failures < settings.lockout.max_failures {
+        return Ok(false);
+    }
+
+    lock_out(username, settings, &mut record)?;
+    write_record(&mut f, &record)?;
+    Ok(true)
+}
+
+/// Clear all failures and lockouts for the user after a successful authentication
+pub fn clear_failures(username: &str, settings: &Settings) -> Result<(), Box<dyn Error>> {
+    if settings.lockout.max_failures == 0 {
+        return Ok(());
+    }
+
+    let (mut f, record) = open_record_locked(username)?;
+    if record.failures != 0 || record.lockouts != 0 {
+        write_record(&mut f, &FailureRecord::default())?;
+    }
+    Ok(())
+}
+
+/// Remove the user's failure record entirely. Used by administrators to lift a lockout.
+pub fn reset_failures(username: &str) -> Result<(), Box<dyn Error>> {
+    // Hold the lock so we don't race with an ongoing authentication
+    let (_f, _) = open_record_locked(username)?;
+    fs::remove_file(format!("{}/{}", FAILURES_PATH, username))?;
+    log_auth_warning(&format!("failed attempts for user {} reset by root", username));
+    Ok(())
+}
@@ -1,14 +1,35 @@
 use std::env;
 use std::io;
+use std::time::Duration;
 use std::error::Error;
 use std::process;
 use std::process::Command;
+use std::path::Path;
 use std::io::Write;
 use std::os::unix::process::CommandExt;
+use std::os::unix::process::ExitStatusExt;
 
+mod keyfile;
 mod session;
+mod lockout;
+mod audit;
+mod hooks;
+use audit::AuditRecord;
+use audit::AuthResult;
+use audit::Decision;
+mod pty;
+#[allow(dead_code)] // Shared with rudo-replay, which reads the recordings
+mod iolog;
+use iolog::IoLogInfo;
+use iolog::IoLogWriter;
+mod edit;
 mod auth;
 use auth::authenticate_current_user_n;
+use auth::AuthError;
+use auth::input::PasswordInput;
+use auth::input::InputMode;
+#[cfg(feature = "totp")]
+use auth::authenticate_chain_n;
 
 mod osutils;
 use osutils::OSUtils;
@@ -16,16 +37,24 @@ use osutils::unix::UnixOSUtils;
 
 mod settings;
 use settings::Settings;
+use settings::Rule;
+use settings::HookEvent;
 
 extern crate time;
 extern crate libc;
 extern crate which;
+extern crate termion;
+extern crate zeroize;
+extern crate hmac;
+extern crate sha2;
+extern crate hex;
 
 extern crate getopts;
 use getopts::Options;
 use getopts::ParsingStyle;
 
 
+extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
@@ -34,14 +63,52 @@ extern crate serde_json;
 pub static CONFIG_PATH: &'static str = "/etc/rudo.json";
 pub static DEFAULT_PROMPT: &'static str = "Password: ";
 pub static SESSION_PATH: &'static str = "/var/run/rudo";
+pub static SESSION_KEY_PATH: &'static str = "/var/run/rudo-session.key";
+pub static TMPFS_SESSION_PATH: &'static str = "/run/rudo-sessions";
+pub static TMPFS_SESSION_KEY_PATH: &'static str = "/run/rudo-sessions.key";
 pub static DEFAULT_SESSION_TIMEOUT: i64 = 900;
+pub static DEFAULT_TARGET_USER: &'static str = "root";
+pub static DEFAULT_PASSWORD_TIMEOUT: u64 = 300;
+pub static IO_LOG_PATH: &'static str = "/var/log/rudo-io";
+pub static DEFAULT_AUDIT_LOG_PATH: &'static str = "/var/log/rudo.log";
+pub static SYSLOG_SOCKET_PATH: &'static str = "/dev/log";
+pub static JOURNALD_SOCKET_PATH: &'static str = "/run/systemd/journal/socket";
+pub static AUDIT_KEY_PATH: &'static str = "/etc/rudo-audit.key";
+pub static AUDIT_CHECKPOINT_INTERVAL: u64 = 100; // Lines between signed checkpoints of the audit log
+pub static DEFAULT_HOOK_TIMEOUT: u64 = 10;
+pub static EDIT_TMP_PATH: &'static str = "/var/tmp"; // Where the copies of files edited with -e are kept
+pub static DEFAULT_EDITOR: &'static str = "vi";
+pub static LOGIN_PATH: &'static str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin"; // PATH of -i shells
+
+// Exit codes for failures that scripts need to tell apart
+pub static EXIT_INTERACTION_REQUIRED: i32 = 2; // -n was given but a password is needed
+pub static EXIT_NO_INPUT: i32 = 3;             // -S was given but stdin had no password
+pub static EXIT_CANCELLED: i32 = 4;            // The user cancelled the prompt
+pub static EXIT_PROMPT_TIMEOUT: i32 = 5;       // Nothing was entered before password_timeout_sec
+
+pub static FAILURES_PATH: &'static str = "/var/run/rudo-failures";
+pub static DEFAULT_LOCKOUT_MAX_FAILURES: u32 = 10;
+pub static DEFAULT_LOCKOUT_SEC: i64 = 60;
+pub static DEFAULT_MAX_LOCKOUT_SEC: i64 = 3600;
+pub static TOTP_SECRETS_PATH: &'static str = "/etc/rudo.totp.json";
 
 fn print_help(program_name: &str, opts: Options) {
-    let brief = format!("Usage: {} [flags] [command]", program_name);
+    let brief = format!("Usage: {} [flags] [command]\n       {} -e [flags] <file>...", program_name, program_name);
     writeln!(&mut io::stderr(), "{}", opts.usage(&brief))
         .expect("Failed to write to stderr!");
 }
 
+/// Map an error that aborted rudo to its exit code
+fn get_error_exit_code(e: &(dyn Error + 'static)) -> i32 {
+    match e.downcast_ref::<AuthError>() {
+        Some(AuthError::InteractionRequired) => EXIT_INTERACTION_REQUIRED,
+        Some(AuthError::NoInput) => EXIT_NO_INPUT,
+        Some(AuthError::Cancelled) => EXIT_CANCELLED,
+        Some(AuthError::Timeout) => EXIT_PROMPT_TIMEOUT,
+        None => 1,
+    }
+}
+
 fn generate_empty_config() {
     // Create a new settings object
     let new_settings = Settings::new();
@@ -54,32 +121,223 @@ fn generate_empty_config() {
     println!("{}", settings_str);
 }
 
+///
+/// Handles enrolling a user for TOTP authentication and prints the provisioning URI.
+/// Only root may enroll other users or replace an existing enrollment.
+///
+#[cfg(feature = "totp")]
+fn totp_enroll<T: OSUtils>(osutils: &T, target: Option<String>, input_mode: InputMode) -> Result<i32, Box<dyn Error>> {
+    // Load the settings file
+    let settings = Settings::from_file(CONFIG_PATH)
+        .expect("Unable to read configuration file! Run --genconfig.");
+    let input = PasswordInput::new(osutils, &settings, input_mode)?;
+
+    let is_root = osutils.get_current_uid()? == 0;
+    let username = match target {
+        Some(ref u) if !is_root => {
+            if *u != osutils.get_username()? {
+                writeln!(&mut io::stderr(), "Only root can enroll other users.")?;
+                return Ok(1);
+            }
+            u.clone()
+        },
+        Some(u) => u,
+        None => osutils.get_username()?,
+    };
+
+    // Confirm the caller's identity with the auth chain minus TOTP itself, since the
+    // user may not have a secret yet
+    let chain: Vec<_> = settings.auth_chain.iter().filter(|e| e.framework != "totp").cloned().collect();
+    if chain.is_empty() {
+        writeln!(&mut io::stderr(), "auth_chain has no frameworks besides TOTP to confirm your identity with.")?;
+        return Ok(1);
+    }
+    let invoker = osutils.get_username()?;
+    let auth_res = authenticate_chain_n::<T>(osutils, &settings, &chain, &invoker, &input, 3)?;
+    if !auth_res {
+        return Ok(1);
+    }
+
+    let uri = auth::totp::enroll_user(&username, &osutils.get_hostname()?, is_root)?;
+    println!("Add the following URI to your authenticator app:");
+    println!("{}", uri);
+    Ok(0)
+}
+
+///
+/// Handles clearing a user's failed attempts and lifting their lockout. Only root may do this.
+///
+fn reset_user_failures<T: OSUtils>(osutils: &T, username: &str) -> Result<i32, Box<dyn Error>> {
+    if osutils.get_current_uid()? != 0 {
+        writeln!(&mut io::stderr(), "Only root can reset failed attempts.")?;
+        return Ok(1);
+    }
+
+    lockout::reset_failures(username)?;
+    println!("Reset failed attempts for {}.", username);
+    Ok(0)
+}
+
+/// Format a UNIX timestamp for display
+fn format_timestamp(timestamp: i64) -> String {
+    time::OffsetDateTime::from_unix_timestamp(timestamp).format("%F %T UTC")
+}
+
+///
+/// Handles --status: shows how long the current user's sessions in the current scope last
+///
+fn show_session_status<T: OSUtils>(osutils: &T) -> Result<i32, Box<dyn Error>> {
+    let settings = Settings::from_file(CONFIG_PATH)?;
+    let username = osutils.get_username()?;
+    let cur_timestamp = time::OffsetDateTime::now_utc().unix_timestamp();
+
+    let sessions: Vec<_> = session::list_sessions(&settings, Some(&username))?
+        .into_iter().filter(|s| s.current).collect();
+    if sessions.is_empty() {
+        println!("No active session. The next command will require authentication.");
+        return Ok(1);
+    }
+
+    for s in sessions.iter() {
+        let tag = s.tag.as_ref().map(|t| format!(" with rules tagged {}", t)).unwrap_or_default();
+        println!("Session ({}) for running commands as {}{}, authenticated as {}, expires in {} seconds (at {}).",
+                 s.scope, s.target_user, tag, s.auth_user, s.end_timestamp - cur_timestamp,
+                 format_timestamp(s.end_timestamp));
+    }
+    Ok(0)
+}
+
+///
+/// Handles --list-sessions: shows the active sessions of one or all users
+///
+fn list_all_sessions<T: OSUtils>(osutils: &T, username: Option<String>) -> Result<i32, Box<dyn Error>> {
+    if osutils.get_current_uid()? != 0 {
+        writeln!(&mut io::stderr(), "Only root can list sessions.")?;
+        return Ok(1);
+    }
+
+    let settings = Settings::from_file(CONFIG_PATH)?;
+    let sessions = session::list_sessions(&settings, username.as_deref())?;
+    if sessions.is_empty() {
+        println!("No active sessions.");
+        return Ok(0);
+    }
+
+    println!("{:<16} {:<10} {:<24} {:<16} {:<16} {:<12} {:<24} EXPIRES",
+             "USER", "ID", "SCOPE", "TARGET", "AUTH USER", "TAG", "STARTED");
+    for s in sessions.iter() {
+        println!("{:<16} {:<10} {:<24} {:<16} {:<16} {:<12} {:<24} {}", s.username, s.id, s.scope, s.target_user,
+                 s.auth_user, s.tag.as_deref().unwrap_or("-"), format_timestamp(s.start_timestamp),
+                 format_timestamp(s.end_timestamp));
+    }
+    Ok(0)
+}
+
+///
+/// Handles --revoke-session: removes one or all sessions of a user
+///
+fn revoke_user_sessions<T: OSUtils>(osutils: &T, username: &str, id: Option<String>) -> Result<i32, Box<dyn Error>> {
+    if osutils.get_current_uid()? != 0 {
+        writeln!(&mut io::stderr(), "Only root can revoke sessions.")?;
+        return Ok(1);
+    }
+
+    let settings = Settings::from_file(CONFIG_PATH)?;
+    let removed = session::revoke_sessions(&settings, username, id.as_deref())?;
+    if removed == 0 {
+        writeln!(&mut io::stderr(), "No matching sessions for {}.", username)?;
+        return Ok(1);
+    }
+    println!("Revoked {} session(s) of {}.", removed, username);
+    Ok(0)
+}
+
+///
+/// Handles --verify-log: checks the given audit logs, oldest first, for signs of tampering
+///
+fn verify_audit_logs<T: OSUtils>(osutils: &T, paths: &[String]) -> Result<i32, Box<dyn Error>> {
+    if osutils.get_current_uid()? != 0 {
+        writeln!(&mut io::stderr(), "Only root can verify audit logs.")?;
+        return Ok(1);
+    }
+
+    let settings = Settings::from_file(CONFIG_PATH)?;
+    let res = audit::chain::verify(paths, &settings.audit_log)?;
+    for warning in res.warnings.iter() {
+        println!("Warning: {}", warning);
+    }
+    if !res.problems.is_empty() {
+        for problem in res.problems.iter() {
+            println!("{}", problem);
+        }
+        println!("The audit log was tampered with!");
+        return Ok(1);
+    }
+    println!("Verified {} records and {} checkpoints.", res.records, res.checkpoints);
+    Ok(0)
+}
+
+///
+/// Handles -v: authenticates the current user if needed and extends their session
+/// for running commands as the given user without running a command
+///
+fn validate_session<T: OSUtils>(osutils: &T, user: Option<String>, input_mode: InputMode) -> Result<i32, Box<dyn Error>> {
+    // Load the settings file
+    let settings = Settings::from_file(CONFIG_PATH)
+        .expect("Unable to read configuration file! Run --genconfig.");
+    let input = PasswordInput::new(osutils, &settings, input_mode)?;
+
+    // Give the user 3 tries to authenticate
+    let username = osutils.get_username()?;
+    let target_user = user.unwrap_or_else(|| String::from(DEFAULT_TARGET_USER));
+    let auth_user = settings.get_auth_user(None, &username, &target_user);
+    let auth_res = authenticate_current_user_n::<T>(osutils, &settings, None, &auth_user, &target_user, &input, 3)?;
+    if !auth_res {
+        return Ok(1);
+    }
+
+    if settings.session_timeout_sec > 0 {
+        session::extend_session(&settings, &username, &auth_user, &target_user)?;
+    }
+    Ok(0)
+}
+
 ///
 /// Handles listing of current user's permissions to STDOUT
 ///
-fn list_permissions<T: OSUtils>(osutils: &T) -> Result<i32, Box<dyn Error>> {
+fn list_permissions<T: OSUtils>(osutils: &T, input_mode: InputMode) -> Result<i32, Box<dyn Error>> {
     // Load the settings file
     let settings = Settings::from_file(CONFIG_PATH)
         .expect("Unable to read configuration file! Run --genconfig.");
+    let input = PasswordInput::new(osutils, &settings, input_mode)?;
 
     // Give the user 3 tries to authenticate
-    let auth_res = authenticate_current_user_n::<T>(osutils, &settings, 3)?;
+    let username = osutils.get_username()?;
+    let auth_user = settings.get_auth_user(None, &username, DEFAULT_TARGET_USER);
+    let auth_res = authenticate_current_user_n::<T>(osutils, &settings, None, &auth_user, DEFAULT_TARGET_USER,
+                                                    &input, 3)?;
     if !auth_res {
         return Ok(1);
     }
 
     // Get this user's User struct
-    let username = osutils.get_username()?;
     let user = settings.get_user(&username)?;
 
     // Create a string of all commands the user can run
     let mut all_commands: String = String::new();
-    for cmd in &user.permissions.allowed_commands {
+    let rule_commands = user.rules.iter().flat_map(|r| r.allowed_commands.iter());
+    for cmd in user.permissions.allowed_commands.iter().chain(rule_commands) {
         all_commands += cmd;
         all_commands += " ";
     }
 
     println!("You are allowed to run the following commands: {}", all_commands);
+
+    let rule_edits = user.rules.iter().flat_map(|r| r.allowed_edits.iter());
+    let all_edits: Vec<&str> = user.permissions.allowed_edits.iter().chain(rule_edits).map(|f| f.as_str()).collect();
+    if !all_edits.is_empty() {
+        println!("You are allowed to edit the following files: {}", all_edits.join(" "));
+    }
     process::exit(0);
 }
 
@@ -87,32 +345,177 @@ fn list_permissions<T: OSUtils>(osutils: &T) -> Result<i32, Box<dyn Error>> {
 /// @param user user to run command as
 /// @param command program to launch
 /// @param args arguments to launch the program with
+/// @param input_mode where to read the password from
 /// @return program return code
-fn run_command<T: OSUtils>(osutils: &T, user: Option<String>, group: Option<String>,  command: &str, args: &Vec<String>)
+fn run_command<T: OSUtils>(osutils: &T, user: Option<String>, group: Option<String>,  command: &str, args: &Vec<String>,
+                           input_mode: InputMode)
     -> Result<i32, Box<dyn Error>> {
 
     // Load the settings file
     let settings = Settings::from_file(CONFIG_PATH)
         .expect("Unable to read configuration file! Run --genconfig.");
+    let input = PasswordInput::new(osutils, &settings, input_mode)?;
 
-    // Give the user 3 tries to authenticate
-    let auth_res = authenticate_current_user_n::<T>(osutils, &settings, 3)?;
-    if !auth_res {
-        return Ok(1);
+    // Look up the rule that allows the command first, since it decides how the user
+    // has to authenticate. A denial is only reported after authentication so that
+    // the policy isn't revealed to unauthenticated callers.
+    let username: String = osutils.get_username()?;
+    let command_match = settings.sanitize_user_command(&username, command);
+    let rule = command_match.as_ref().ok().and_then(|m| m.rule);
+
+    // Determine whose credentials are needed, then give the user 3 tries to authenticate
+    let target_user = user.clone().unwrap_or_else(|| String::from(DEFAULT_TARGET_USER));
+    let mut record = AuditRecord::new(osutils, command, args, &target_user, group.clone())?;
+    match command_match {
+        Ok(ref m) => {
+            record.decision = Some(Decision::Allow);
+            record.path = Some(m.path.clone());
+            record.rule = Some(settings.describe_rule(&username, rule));
+            record.tag = rule.and_then(|r| r.tag.clone());
+        },
+        Err(ref e) => {
+            record.decision = Some(Decision::Deny);
+            record.reason = Some(e.to_string());
+        },
+    }
+    let auth_user = settings.get_auth_user(rule, &username, &target_user);
+    let auth_res = authenticate_current_user_n::<T>(osutils, &settings, rule, &auth_user, &target_user, &input, 3);
+    if !authenticated(&settings, &mut record, &auth_res) {
+        return auth_res.map(|_| 1);
     }
 
     // Confirm that user is in the settings file and has permission
-    let username: String = osutils.get_username()?;
-    let safe_command_path = match settings.sanitize_user_command(&username, command) {
-        Ok(v) => v,
-        Err(e) => {
-            dbg!(e);
-            writeln!(&mut io::stderr(), "You don't have permission to run that! This incident won't be reported.")
+    let safe_command_path = match command_match {
+        Ok(v) => v.path,
+        Err(_) => {
+            deny(&settings, &record);
+            writeln!(&mut io::stderr(), "You don't have permission to run that! This incident will be reported.")
                 .unwrap();
             return Ok(1);
         }
     };
 
+    // Determine the uid and gid to impersonate
+    let (uid, gid) = get_target_ids(osutils, user, group)?;
+
+    // Now that the user is authenticated, run the provided command
+    let iolog = start_io_log(rule, &username, &target_user, &safe_command_path, args)?;
+    let mut cmd = Command::new(safe_command_path);
+    cmd.args(args).uid(uid).gid(gid);
+    match launch(&settings, &mut cmd, iolog, rule.and_then(|r| r.timeout_sec), &mut record) {
+        Ok(code) => Ok(code),
+        Err(e) => {
+            // If we got here, it means the command failed
+            match e.downcast_ref::<io::Error>() {
+                Some(_) => writeln!(&mut io::stderr(), "rudo: {}: command not found", &command),
+                None => writeln!(&mut io::stderr(), "rudo: {}", e),
+            }.unwrap();
+            Ok(1)
+        }
+    }
+}
+
+/// Record the result of authentication. Returns whether the user was authenticated,
+/// otherwise the attempt is logged right away since nothing else is going to happen.
+fn authenticated(settings: &Settings, record: &mut AuditRecord, auth_res: &Result<bool, Box<dyn Error>>) -> bool {
+    record.auth = Some(match *auth_res {
+        Ok(true) => AuthResult::Success,
+        Ok(false) => AuthResult::Failure,
+        Err(_) => AuthResult::Error,
+    });
+    if record.auth == Some(AuthResult::Success) {
+        return true;
+    }
+    log_denial(settings, record);
+    if record.auth == Some(AuthResult::Failure) {
+        hooks::run_hooks(settings, HookEvent::AuthFailure, record.tag.as_deref(), record);
+    }
+    false
+}
+
+/// Log a command that the policy doesn't allow and notify the hooks
+fn deny(settings: &Settings, record: &AuditRecord) {
+    log_denial(settings, record);
+    hooks::run_hooks(settings, HookEvent::Denied, record.tag.as_deref(), record);
+}
+
+/// Write the record of a request that's not going to run anything to the audit log.
+/// A failure is reported, but doesn't change the outcome.
+fn log_denial(settings: &Settings, record: &AuditRecord) {
+    if let Err(e) = audit::log(settings, record) {
+        writeln!(&mut io::stderr(), "rudo: {}", e).unwrap();
+    }
+}
+
+/// Start recording the I/O of a command if the rule that allows it has `log_io` set
+fn start_io_log(rule: Option<&Rule>, username: &str, target_user: &str, command: &str, args: &[String])
+    -> Result<Option<IoLogWriter>, Box<dyn Error>> {
+    if !rule.is_some_and(|r| r.log_io) {
+        return Ok(None);
+    }
+
+    let (rows, cols) = pty::get_window_size().unwrap_or((0, 0));
+    let info = IoLogInfo {
+        user: username.to_string(),
+        target_user: target_user.to_string(),
+        command: command.to_string(),
+        args: args.to_vec(),
+        cwd: env::current_dir()?.to_string_lossy().into_owned(),
+        tty: session::get_cur_tty_name()?,
+        start_timestamp: time::OffsetDateTime::now_utc().unix_timestamp(),
+        rows,
+        cols,
+        exit_code: None,
+    };
+    Ok(Some(IoLogWriter::create(IO_LOG_PATH, info)?))
+}
+
+/// Run the command, either in place of rudo or as its child if `monitor` or `use_pty` is set,
+/// its I/O is recorded or it has a timeout. Only returns if rudo waited for the command, with
+/// its exit code, or if it couldn't be started. The record is written to the audit log before
+/// the command starts, and if rudo waits for it, a second record with its exit status once it exits.
+fn launch(settings: &Settings, cmd: &mut Command, iolog: Option<IoLogWriter>, timeout_sec: Option<u64>,
+          record: &mut AuditRecord) -> Result<i32, Box<dyn Error>> {
+    // Log the command before it starts, so it's on record even if rudo is killed before it exits
+    audit::log(settings, record)?;
+    hooks::run_hooks(settings, HookEvent::Executed, record.tag.as_deref(), record);
+
+    let use_pty = settings.use_pty && pty::has_terminal();
+    if iolog.is_none() && timeout_sec.is_none() && !use_pty && !settings.monitor {
+        return Err(Box::new(cmd.exec()));
+    }
+
+    // Recorded commands always get a pty if there's a terminal, so the user's terminal
+    // doesn't have to be shared with them
+    let timeout = timeout_sec.map(Duration::from_secs);
+    let res = match iolog {
+        Some(mut log) => pty::supervise(cmd, true, Some(&mut log), timeout).and_then(|exit| {
+            log.finish(exit.code())?;
+            Ok(exit)
+        }),
+        None => pty::supervise(cmd, use_pty, None, timeout),
+    };
+    if let Ok(ref exit) = res {
+        record.timestamp = time::OffsetDateTime::now_utc().format("%FT%TZ");
+        record.completed = true;
+        record.exit_status = Some(exit.code());
+        record.signal = exit.status.signal();
+        record.timed_out = exit.timed_out;
+        // The command has already run, so its exit code is returned even if this can't be logged
+        if let Err(e) = audit::log(settings, record) {
+            writeln!(&mut io::stderr(), "rudo: {}", e).unwrap();
+        }
+    }
+    if let (Ok(true), Some(sec)) = (res.as_ref().map(|e| e.timed_out), timeout_sec) {
+        writeln!(&mut io::stderr(), "rudo: command timed out after {} seconds", sec)?;
+    }
+    res.map(|exit| exit.code())
+}
+
+/// Get the uid and gid to run as. Defaults to root, with the group overriding
+/// the user's primary group if given.
+fn get_target_ids<T: OSUtils>(osutils: &T, user: Option<String>, group: Option<String>)
+    -> Result<(u32, u32), Box<dyn Error>> {
     // Determine the uid of the user to impersonate
     let mut uid: u32 = 0;
     let mut gid: u32 = 0;
@@ -127,12 +530,223 @@ fn run_command<T: OSUtils>(osutils: &T, user: Option<String>, group: Option<Stri
     	gid = osutils.get_gid_by_groupname(&groupname)?;
     }
 
-    // Now that the user is authenticated, run the provided command
-    Command::new(safe_command_path).args(args).uid(uid).gid(gid).exec();
+    Ok((uid, gid))
+}
+
+/// Quote an argument for a shell's -c. Everything but a single quote is literal inside single
+/// quotes, so those are closed, escaped and reopened.
+fn escape_shell_arg(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
 
-    // If we got here, it means the command failed
-    writeln!(&mut io::stderr(), "rudo: {}: command not found", &command).unwrap();
-    Ok(1)
+/// Handles -i and -s - Authenticate and run a shell
+/// @param user user to run the shell as
+/// @param login run the target user's shell as a login shell in their home directory (-i),
+///              instead of $SHELL in the current directory (-s)
+/// @param args command to pass to the shell's -c, if any
+/// @param input_mode where to read the password from
+/// @return program return code
+fn run_shell<T: OSUtils>(osutils: &T, user: Option<String>, group: Option<String>, login: bool, args: &[String],
+                         input_mode: InputMode)
+    -> Result<i32, Box<dyn Error>> {
+
+    // Load the settings file
+    let settings = Settings::from_file(CONFIG_PATH)
+        .expect("Unable to read configuration file! Run --genconfig.");
+    let input = PasswordInput::new(osutils, &settings, input_mode)?;
+
+    // Shells need explicit permission, since they allow running anything.
+    // As with commands, a denial is only reported after authentication.
+    let username: String = osutils.get_username()?;
+    let shell_match = settings.sanitize_user_shell(&username);
+    let rule = shell_match.as_ref().ok().and_then(|r| *r);
+
+    // Determine the shell and its arguments, so that they can be logged
+    let target_user = user.clone().unwrap_or_else(|| String::from(DEFAULT_TARGET_USER));
+    let (home, target_shell) = osutils.get_home_and_shell(&target_user)?;
+    let shell = if login {
+        target_shell
+    } else {
+        env::var("SHELL").ok().filter(|s| !s.is_empty()).unwrap_or(target_shell)
+    };
+
+    let mut shell_args = Vec::new();
+    if !args.is_empty() {
+        let escaped: Vec<String> = args.iter().map(|a| escape_shell_arg(a)).collect();
+        shell_args.push(String::from("-c"));
+        shell_args.push(escaped.join(" "));
+    }
+
+    let mut record = AuditRecord::new(osutils, &shell, &shell_args, &target_user, group.clone())?;
+    match shell_match {
+        Ok(_) => {
+            record.decision = Some(Decision::Allow);
+            record.path = Some(shell.clone());
+            record.rule = Some(settings.describe_rule(&username, rule));
+            record.tag = rule.and_then(|r| r.tag.clone());
+        },
+        Err(ref e) => {
+            record.decision = Some(Decision::Deny);
+            record.reason = Some(e.to_string());
+        },
+    }
+
+    // Determine whose credentials are needed, then give the user 3 tries to authenticate
+    let auth_user = settings.get_auth_user(rule, &username, &target_user);
+    let auth_res = authenticate_current_user_n::<T>(osutils, &settings, rule, &auth_user, &target_user, &input, 3);
+    if !authenticated(&settings, &mut record, &auth_res) {
+        return auth_res.map(|_| 1);
+    }
+
+    if shell_match.is_err() {
+        deny(&settings, &record);
+        writeln!(&mut io::stderr(), "You don't have permission to run a shell! This incident will be reported.")
+            .unwrap();
+        return Ok(1);
+    }
+
+    let (uid, gid) = get_target_ids(osutils, user, group)?;
+    let mut shell_command = Command::new(&shell);
+    shell_command.args(&shell_args);
+    if login {
+        // A leading dash in argv[0] makes the shell act as a login shell
+        let shell_name = Path::new(&shell).file_name().map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| shell.clone());
+        // Like a real login, the shell starts from a clean environment
+        let term = env::var_os("TERM");
+        shell_command.arg0(format!("-{}", shell_name))
+            .current_dir(&home)
+            .env_clear()
+            .envs(term.map(|t| ("TERM", t)))
+            .env("PATH", LOGIN_PATH)
+            .env("HOME", &home)
+            .env("SHELL", &shell)
+            .env("USER", &target_user)
+            .env("LOGNAME", &target_user);
+    }
+
+    // Now that the user is authenticated, run the shell
+    let iolog = start_io_log(rule, &username, &target_user, &shell, &shell_args)?;
+    shell_command.uid(uid).gid(gid);
+    match launch(&settings, &mut shell_command, iolog, rule.and_then(|r| r.timeout_sec), &mut record) {
+        Ok(code) => Ok(code),
+        Err(e) => {
+            // If we got here, it means the shell failed
+            writeln!(&mut io::stderr(), "rudo: {}: {}", &shell, e).unwrap();
+            Ok(1)
+        }
+    }
+}
+
+/// Handles -e and rudoedit - Authenticate and edit files as the target user
+/// @param user user to edit the files as
+/// @param files files to edit
+/// @param input_mode where to read the password from
+/// @return program return code
+fn edit_files<T: OSUtils>(osutils: &T, user: Option<String>, group: Option<String>, files: &[String],
+                          input_mode: InputMode)
+    -> Result<i32, Box<dyn Error>> {
+
+    // Load the settings file
+    let settings = Settings::from_file(CONFIG_PATH)
+        .expect("Unable to read configuration file! Run --genconfig.");
+    let input = PasswordInput::new(osutils, &settings, input_mode)?;
+
+    // Check the policy against the absolute paths, so relative paths can't get around it.
+    // As with commands, a denial is only reported after authentication.
+    let username: String = osutils.get_username()?;
+    let paths = files.iter().map(|f| edit::resolve_path(f)).collect::<Result<Vec<_>, _>>()?;
+    let edit_match = settings.sanitize_user_edit(&username, &paths);
+    let rule = edit_match.as_ref().ok().and_then(|r| *r);
+
+    let target_user = user.clone().unwrap_or_else(|| String::from(DEFAULT_TARGET_USER));
+    let mut record = AuditRecord::new(osutils, "rudoedit", &paths, &target_user, group.clone())?;
+    match edit_match {
+        Ok(_) => {
+            record.decision = Some(Decision::Allow);
+            record.rule = Some(settings.describe_rule(&username, rule));
+            record.tag = rule.and_then(|r| r.tag.clone());
+        },
+        Err(ref e) => {
+            record.decision = Some(Decision::Deny);
+            record.reason = Some(e.to_string());
+        },
+    }
+
+    // Determine whose credentials are needed, then give the user 3 tries to authenticate
+    let auth_user = settings.get_auth_user(rule, &username, &target_user);
+    let auth_res = authenticate_current_user_n::<T>(osutils, &settings, rule, &auth_user, &target_user, &input, 3);
+    if !authenticated(&settings, &mut record, &auth_res) {
+        return auth_res.map(|_| 1);
+    }
+
+    if edit_match.is_err() {
+        deny(&settings, &record);
+        writeln!(&mut io::stderr(), "You don't have permission to edit that! This incident will be reported.")
+            .unwrap();
+        return Ok(1);
+    }
+
+    // Files the user could swap for others while they're edited are refused. Root can
+    // write anywhere already, so there's nothing to check for them.
+    let invoker = (osutils.get_current_uid()?, osutils.get_current_gid()?);
+    if let Err(e) = paths.iter().try_for_each(|p| edit::check_path(p, invoker.0 != 0)) {
+        record.decision = Some(Decision::Deny);
+        record.reason = Some(e.to_string());
+        deny(&settings, &record);
+        writeln!(&mut io::stderr(), "rudo: {}", e).unwrap();
+        return Ok(1);
+    }
+
+    let target = get_target_ids(osutils, user, group)?;
+    audit::log(&settings, &record)?;
+    hooks::run_hooks(&settings, HookEvent::Executed, record.tag.as_deref(), &record);
+
+    let mut edits = Vec::new();
+    for path in paths.iter() {
+        match edit::start_edit(path, target, invoker) {
+            Ok(file) => edits.push(file),
+            Err(e) => {
+                edits.iter().for_each(|f| f.discard());
+                writeln!(&mut io::stderr(), "rudo: {}: {}", path, e).unwrap();
+                return Ok(1);
+            },
+        }
+    }
+
+    // The editor runs as the invoking user, with their environment
+    let editor = edit::get_editor();
+    let mut editor_command = Command::new(&editor[0]);
+    editor_command.args(&editor[1..]).args(edits.iter().map(|f| &f.tmp_path)).uid(invoker.0).gid(invoker.1);
+    let code = match pty::supervise(&mut editor_command, false, None, None) {
+        Ok(exit) => exit.code(),
+        Err(e) => {
+            edits.iter().for_each(|f| f.discard());
+            writeln!(&mut io::stderr(), "rudo: {}: {}", &editor[0], e).unwrap();
+            return Ok(1);
+        },
+    };
+    if code != 0 {
+        writeln!(&mut io::stderr(), "rudo: {} exited with {}, so nothing was saved", &editor[0], code).unwrap();
+        for file in edits.iter() {
+            writeln!(&mut io::stderr(), "rudo: your changes to {} are left in {}", file.path, file.tmp_path).unwrap();
+        }
+        return Ok(1);
+    }
+
+    let mut res = 0;
+    for file in edits.iter() {
+        match edit::finish_edit(file, target, invoker) {
+            Ok(true) => {},
+            Ok(false) => writeln!(&mut io::stderr(), "rudo: {} unchanged", file.path).unwrap(),
+            Err(e) => {
+                writeln!(&mut io::stderr(), "rudo: failed to save {}: {}", file.path, e).unwrap();
+                writeln!(&mut io::stderr(), "rudo: your changes are left in {}", file.tmp_path).unwrap();
+                res = 1;
+            },
+        }
+    }
+    Ok(res)
 }
 
 fn main() {
@@ -149,7 +763,23 @@ fn main() {
     opts.optflag("l", "list", "list all permissions for current user");
     opts.optopt("u", "user", "run as the specified user", "<user>");
     opts.optopt("g", "group", "run as the specified group", "<group>");
+    opts.optflag("A", "askpass", "read the password with the askpass helper instead of the TTY");
+    opts.optflag("S", "stdin", "read the password from STDIN instead of the TTY");
+    opts.optflag("n", "non-interactive", "fail instead of prompting if a password is required");
+    opts.optflag("i", "login", "run the target user's login shell, with the command if one is given");
+    opts.optflag("s", "shell", "run $SHELL or the target user's shell, with the command if one is given");
+    opts.optflag("e", "edit", "edit the given files as the target user with your own editor, like running rudoedit");
+    opts.optflag("k", "reset-timestamp", "invalidate the session on this TTY, before running the command if one is given");
+    opts.optflag("K", "remove-timestamp", "remove all of your sessions");
+    opts.optflag("v", "validate", "authenticate and extend the session without running a command");
     opts.optflag("", "genconfig", "Generate an empty config and output to STDOUT");
+    opts.optflag("", "status", "show how long your session on this TTY lasts");
+    opts.optflag("", "list-sessions", "list active sessions of all users, or the given user (root only)");
+    opts.optopt("", "revoke-session", "remove all sessions of a user, or only the one with the given id (root only)", "<user>");
+    opts.optopt("", "verify-log", "check an audit log for tampering, after the older logs given as arguments (root only)", "<file>");
+    opts.optopt("", "reset-failures", "clear failed attempts and lockouts of a user (root only)", "<user>");
+    #[cfg(feature = "totp")]
+    opts.optflag("", "totp-enroll", "enroll a TOTP secret for the current user (or the given user, as root)");
 
     // Instantiate platform OSUtils
     let osutils = UnixOSUtils::new();
@@ -167,11 +797,66 @@ fn main() {
         process::exit(0);
     }
 
+    // Determine where passwords are read from
+    let input_mode = match (matches.opt_present("A"), matches.opt_present("S"), matches.opt_present("n")) {
+        (false, false, false) => InputMode::Tty,
+        (true, false, false) => InputMode::Askpass,
+        (false, true, false) => InputMode::Stdin,
+        (false, false, true) => InputMode::NonInteractive,
+        _ => {
+            writeln!(&mut io::stderr(), "Only one of -A, -S and -n may be given.").unwrap();
+            process::exit(1);
+        }
+    };
+
     // Handle --list
     if matches.opt_present("l") {
-        let res = list_permissions(&osutils).unwrap_or_else(|e|{
+        let res = list_permissions(&osutils, input_mode).unwrap_or_else(|e|{
             writeln!(&mut io::stderr(), "Failed to list permissions: {}", e).unwrap();
+            process::exit(get_error_exit_code(e.as_ref()));
+        });
+        process::exit(res);
+    }
+
+    // -K and -v never run a command
+    if (matches.opt_present("K") || matches.opt_present("v")) && !matches.free.is_empty() {
+        writeln!(&mut io::stderr(), "-K and -v can't be used with a command!").unwrap();
+        process::exit(1);
+    }
+
+    // Handle -K
+    if matches.opt_present("K") {
+        let res = osutils.get_username().and_then(|u| {
+            let settings = Settings::from_file(CONFIG_PATH)?;
+            session::remove_all_sessions(&settings, &u)
+        });
+        if let Err(e) = res {
+            writeln!(&mut io::stderr(), "Failed to remove sessions: {}", e).unwrap();
+            process::exit(1);
+        }
+        process::exit(0);
+    }
+
+    // Handle -k. Without a command there's nothing else to do.
+    if matches.opt_present("k") {
+        let res = osutils.get_username().and_then(|u| {
+            let settings = Settings::from_file(CONFIG_PATH)?;
+            session::invalidate_session(&settings, &u)
+        });
+        if let Err(e) = res {
+            writeln!(&mut io::stderr(), "Failed to invalidate session: {}", e).unwrap();
             process::exit(1);
+        }
+        if matches.free.is_empty() && !matches.opt_present("i") && !matches.opt_present("s") {
+            process::exit(0);
+        }
+    }
+
+    // Handle -v
+    if matches.opt_present("v") {
+        let res = validate_session(&osutils, matches.opt_str("u"), input_mode).unwrap_or_else(|e| {
+            writeln!(&mut io::stderr(), "Failed to validate session: {}", e).unwrap();
+            process::exit(get_error_exit_code(e.as_ref()));
         });
         process::exit(res);
     }
@@ -182,9 +867,66 @@ fn main() {
         process::exit(0);
     }
 
-    if matches.free.len() < 1 {
-        print_help(&program_name, opts);
-        process::exit(1);
+    // Handle --status
+    if matches.opt_present("status") {
+        let res = show_session_status(&osutils).unwrap_or_else(|e| {
+            writeln!(&mut io::stderr(), "Failed to get session status: {}", e).unwrap();
+            process::exit(1);
+        });
+        process::exit(res);
+    }
+
+    // Handle --list-sessions
+    if matches.opt_present("list-sessions") {
+        let target = matches.free.first().cloned();
+        let res = list_all_sessions(&osutils, target).unwrap_or_else(|e| {
+            writeln!(&mut io::stderr(), "Failed to list sessions: {}", e).unwrap();
+            process::exit(1);
+        });
+        process::exit(res);
+    }
+
+    // Handle --revoke-session
+    if let Some(username) = matches.opt_str("revoke-session") {
+        let id = matches.free.first().cloned();
+        let res = revoke_user_sessions(&osutils, &username, id).unwrap_or_else(|e| {
+            writeln!(&mut io::stderr(), "Failed to revoke sessions: {}", e).unwrap();
+            process::exit(1);
+        });
+        process::exit(res);
+    }
+
+    // Handle --verify-log
+    if let Some(path) = matches.opt_str("verify-log") {
+        let mut paths = matches.free.clone();
+        paths.push(path);
+        let res = verify_audit_logs(&osutils, &paths).unwrap_or_else(|e| {
+            writeln!(&mut io::stderr(), "Failed to verify audit log: {}", e).unwrap();
+            process::exit(1);
+        });
+        process::exit(res);
+    }
+
+    // Handle --reset-failures
+    if let Some(username) = matches.opt_str("reset-failures") {
+        let res = reset_user_failures(&osutils, &username).unwrap_or_else(|e| {
+            writeln!(&mut io::stderr(), "Failed to reset failed attempts: {}", e).unwrap();
+            process::exit(1);
+        });
+        process::exit(res);
+    }
+
+    // Handle --totp-enroll
+    #[cfg(feature = "totp")]
+    {
+        if matches.opt_present("totp-enroll") {
+            let target = matches.free.first().cloned();
+            let res = totp_enroll(&osutils, target, input_mode).unwrap_or_else(|e| {
+                writeln!(&mut io::stderr(), "Failed to enroll TOTP secret: {}", e).unwrap();
+                process::exit(get_error_exit_code(e.as_ref()));
+            });
+            process::exit(res);
+        }
     }
 
     // Handle --user
@@ -205,12 +947,49 @@ fn main() {
     	};
     }
 
+    // Handle -e. Invoked as rudoedit, rudo always edits files.
+    let invoked_as_edit = Path::new(&program_name).file_name().is_some_and(|n| n == "rudoedit");
+    if matches.opt_present("e") || invoked_as_edit {
+        if matches.opt_present("i") || matches.opt_present("s") {
+            writeln!(&mut io::stderr(), "-e can't be used with -i or -s.").unwrap();
+            process::exit(1);
+        }
+        if matches.free.is_empty() {
+            print_help(&program_name, opts);
+            process::exit(1);
+        }
+        let res = edit_files(&osutils, user, group, &matches.free, input_mode).unwrap_or_else(|e| {
+            writeln!(&mut io::stderr(), "Failed to edit files: {}", e).unwrap();
+            process::exit(get_error_exit_code(e.as_ref()));
+        });
+        process::exit(res);
+    }
+
+    // Handle -i and -s
+    if matches.opt_present("i") || matches.opt_present("s") {
+        if matches.opt_present("i") && matches.opt_present("s") {
+            writeln!(&mut io::stderr(), "Only one of -i and -s may be given.").unwrap();
+            process::exit(1);
+        }
+        let login = matches.opt_present("i");
+        let res = run_shell(&osutils, user, group, login, &matches.free, input_mode).unwrap_or_else(|e| {
+            writeln!(&mut io::stderr(), "Failed to run shell: {}", e).unwrap();
+            process::exit(get_error_exit_code(e.as_ref()));
+        });
+        process::exit(res);
+    }
+
+    if matches.free.len() < 1 {
+        print_help(&program_name, opts);
+        process::exit(1);
+    }
+
     // Handle default behavior (run command) 
     let command = matches.free[0].clone();
     matches.free.remove(0);
-    let res = run_command(&osutils, user, group, &command, &matches.free).unwrap_or_else(|e| {
+    let res = run_command(&osutils, user, group, &command, &matches.free, input_mode).unwrap_or_else(|e| {
         writeln!(&mut io::stderr(), "Failed to run command: {}", e).unwrap();
-        process::exit(1);
+        process::exit(get_error_exit_code(e.as_ref()));
     });
 
     process::exit(res);
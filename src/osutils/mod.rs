@@ -14,4 +14,13 @@ pub trait OSUtils {
 
     /// Get the uid for the current user
     fn get_current_uid(&self) -> Result<u32, Box<dyn Error>>;
+
+    /// Get the primary gid for the current user
+    fn get_current_gid(&self) -> Result<u32, Box<dyn Error>>;
+
+    /// Get the home directory and login shell for the given username
+    fn get_home_and_shell(&self, username: &str) -> Result<(String, String), Box<dyn Error>>;
+
+    /// Get the hostname of this machine
+    fn get_hostname(&self) -> Result<String, Box<dyn Error>>;
 }
@@ -1,10 +1,15 @@
 use std::error::Error;
+use std::ffi::CStr;
+
+use libc::{gethostname, c_char};
 
 extern crate users;
 use self::users::get_user_by_name;
 use self::users::get_group_by_name;
 use self::users::get_user_by_uid;
 use self::users::get_current_uid;
+use self::users::get_current_gid;
+use self::users::os::unix::UserExt;
 
 use osutils::OSUtils;
 
@@ -56,4 +61,30 @@ impl OSUtils for UnixOSUtils {
     fn get_current_uid(&self) -> Result<u32, Box<dyn Error>> {
         Ok(get_current_uid())
     }
+
+    fn get_current_gid(&self) -> Result<u32, Box<dyn Error>> {
+        Ok(get_current_gid())
+    }
+
+    fn get_home_and_shell(&self, username: &str) -> Result<(String, String), Box<dyn Error>> {
+        match get_user_by_name(username) {
+            Some(u) => {
+                Ok((u.home_dir().to_string_lossy().into_owned(), u.shell().to_string_lossy().into_owned()))
+            },
+
+            None => {
+                Err(From::from("Failed to obtain home directory and shell for given username."))
+            }
+        }
+    }
+
+    fn get_hostname(&self) -> Result<String, Box<dyn Error>> {
+        let mut buf = [0 as c_char; 256];
+        unsafe {
+            if gethostname(buf.as_mut_ptr(), buf.len() - 1) != 0 {
+                return Err(From::from("Failed to obtain hostname."));
+            }
+            Ok(CStr::from_ptr(buf.as_ptr()).to_string_lossy().into_owned())
+        }
+    }
 } 
@@ -0,0 +1,587 @@
+//!
+//! Supervising commands instead of letting them replace rudo: running them under a new
+//! pseudo-terminal, so they can't reach the user's terminal, recording their I/O, and
+//! passing on signals and the exit status
+//!
+
+use std::io;
+use std::ptr;
+use std::fs::File;
+use std::error::Error;
+use std::io::Read;
+use std::io::Write;
+use std::mem::MaybeUninit;
+use std::process::Command;
+use std::process::ExitStatus;
+use std::process::Stdio;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::io::FromRawFd;
+use std::os::unix::io::RawFd;
+use std::os::unix::process::CommandExt;
+use std::os::unix::process::ExitStatusExt;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::time::{Duration, Instant};
+
+use termion::get_tty;
+use iolog::IoLogWriter;
+use iolog::Stream;
+use libc::{c_int, c_void, termios, winsize, pid_t, siginfo_t};
+use libc::{openpty, tcgetattr, tcsetattr, cfmakeraw, ioctl, TCSAFLUSH, TIOCGWINSZ, TIOCSWINSZ, TIOCSCTTY};
+use libc::{fcntl, pipe, F_GETFD, F_SETFD, F_GETFL, F_SETFL, F_DUPFD_CLOEXEC, FD_CLOEXEC, O_NONBLOCK};
+use libc::{poll, pollfd, POLLIN, POLLOUT, EINTR};
+use libc::{sigaction, sigemptyset, kill, waitpid, SA_SIGINFO, WNOHANG, WUNTRACED};
+use libc::{SIGTERM, SIGHUP, SIGINT, SIGQUIT, SIGUSR1, SIGUSR2, SIGWINCH, SIGCHLD, SIGSTOP, SIGTSTP, SIGCONT, SIGKILL};
+
+// How long to wait for I/O before checking on the child again, in milliseconds
+const POLL_INTERVAL_MS: c_int = 200;
+
+// How long a command that timed out has to exit after SIGTERM before it's killed
+const TIMEOUT_GRACE_SEC: u64 = 5;
+
+// Exit code of a command that timed out, as with timeout(1)
+const TIMEOUT_EXIT_CODE: i32 = 124;
+
+/// Signals sent to a command that timed out, one grace period apart
+static TIMEOUT_SIGNALS: [c_int; 2] = [SIGTERM, SIGKILL];
+
+// State shared with the signal handlers
+static CHILD_PID: AtomicI32 = AtomicI32::new(0);
+static WINDOW_CHANGED: AtomicBool = AtomicBool::new(false);
+static HAS_PTY: AtomicBool = AtomicBool::new(false);
+
+/// Signals sent to rudo that are passed on to the command
+static FORWARDED_SIGNALS: [c_int; 8] = [SIGTERM, SIGHUP, SIGINT, SIGQUIT, SIGUSR1, SIGUSR2, SIGTSTP, SIGCONT];
+
+/// Send a signal that rudo received on to the command
+extern "C" fn forward_signal(sig: c_int, info: *mut siginfo_t, _context: *mut c_void) {
+    let pid = CHILD_PID.load(Ordering::SeqCst);
+    if pid <= 0 {
+        return;
+    }
+
+    let has_pty = HAS_PTY.load(Ordering::SeqCst);
+    // Without a pty, the command shares rudo's terminal and process group, so it already got
+    // the signals the kernel sent for the terminal, e.g. for ^C. Only pass on signals that
+    // were sent to rudo by other processes.
+    let from_process = info.is_null() || unsafe { (*info).si_code } <= 0;
+    if !has_pty && !from_process {
+        return;
+    }
+    // The command leads its own session under a pty, where SIGTSTP from outside is discarded
+    let sig = if has_pty && sig == SIGTSTP { SIGSTOP } else { sig };
+    unsafe { kill(pid, sig); }
+}
+
+/// The user's terminal was resized. The relay loop copies the new size to the pty.
+extern "C" fn set_window_changed(_sig: c_int) {
+    WINDOW_CHANGED.store(true, Ordering::SeqCst);
+}
+
+/// Only there to interrupt poll() when the command exits or stops
+extern "C" fn ignore_signal(_sig: c_int) {}
+
+/// Installs the relay's signal handlers and restores the previous ones when dropped
+struct SignalGuard {
+    old_actions: Vec<(c_int, libc::sigaction)>,
+}
+
+impl SignalGuard {
+    /// Without a pty, window size changes reach the command directly and are
+    /// forwarded like any other signal
+    fn new(has_pty: bool) -> SignalGuard {
+        HAS_PTY.store(has_pty, Ordering::SeqCst);
+        let mut guard = SignalGuard { old_actions: Vec::new() };
+        let forward = forward_signal as extern "C" fn(c_int, *mut siginfo_t, *mut c_void) as usize;
+        let mut handlers: Vec<(c_int, usize, c_int)> =
+            FORWARDED_SIGNALS.iter().map(|&sig| (sig, forward, SA_SIGINFO)).collect();
+        if has_pty {
+            handlers.push((SIGWINCH, set_window_changed as extern "C" fn(c_int) as usize, 0));
+        } else {
+            handlers.push((SIGWINCH, forward, SA_SIGINFO));
+        }
+        handlers.push((SIGCHLD, ignore_signal as extern "C" fn(c_int) as usize, 0));
+
+        for (sig, handler, flags) in handlers {
+            unsafe {
+                // No SA_RESTART, so poll() returns early to handle the signal
+                let mut action: libc::sigaction = MaybeUninit::zeroed().assume_init();
+                action.sa_sigaction = handler;
+                action.sa_flags = flags;
+                sigemptyset(&mut action.sa_mask);
+
+                let mut old_action: libc::sigaction = MaybeUninit::zeroed().assume_init();
+                if sigaction(sig, &action, &mut old_action) == 0 {
+                    guard.old_actions.push((sig, old_action));
+                }
+            }
+        }
+        guard
+    }
+}
+
+impl Drop for SignalGuard {
+    fn drop(&mut self) {
+        for &(sig, ref old_action) in self.old_actions.iter() {
+            unsafe { sigaction(sig, old_action, ptr::null_mut()); }
+        }
+    }
+}
+
+/// Puts the user's terminal into raw mode, so every keystroke is passed to the pty
+/// untouched, and restores the original mode when dropped
+struct RawModeGuard {
+    fd: RawFd,
+    saved: termios,
+}
+
+impl RawModeGuard {
+    fn new(fd: RawFd, saved: termios) -> Result<RawModeGuard, Box<dyn Error>> {
+        let guard = RawModeGuard { fd, saved };
+        guard.enter()?;
+        Ok(guard)
+    }
+
+    fn enter(&self) -> Result<(), Box<dyn Error>> {
+        let mut raw = self.saved;
+        unsafe {
+            cfmakeraw(&mut raw);
+            if tcsetattr(self.fd, TCSAFLUSH, &raw) != 0 {
+                return Err(Box::new(io::Error::last_os_error()));
+            }
+        }
+        Ok(())
+    }
+
+    fn leave(&self) {
+        unsafe { tcsetattr(self.fd, TCSAFLUSH, &self.saved); }
+    }
+}
+
+impl Drop for RawModeGuard {
+    fn drop(&mut self) {
+        self.leave();
+    }
+}
+
+/// Set the close-on-exec flag, so the command doesn't inherit the fd
+fn set_cloexec(fd: RawFd) -> Result<(), Box<dyn Error>> {
+    unsafe {
+        let flags = fcntl(fd, F_GETFD);
+        if flags < 0 || fcntl(fd, F_SETFD, flags | FD_CLOEXEC) < 0 {
+            return Err(Box::new(io::Error::last_os_error()));
+        }
+    }
+    Ok(())
+}
+
+/// Get the rows and columns of the user's terminal, if there is one
+pub fn get_window_size() -> Option<(u16, u16)> {
+    let tty = get_tty().ok()?;
+    unsafe {
+        let mut size: winsize = MaybeUninit::zeroed().assume_init();
+        if ioctl(tty.as_raw_fd(), TIOCGWINSZ, &mut size) != 0 {
+            return None;
+        }
+        Some((size.ws_row, size.ws_col))
+    }
+}
+
+/// Whether rudo has a terminal that a pty could stand in for
+pub fn has_terminal() -> bool {
+    get_tty().is_ok()
+}
+
+/// Copy the size of the user's terminal to the pty and return the new rows and columns
+fn copy_window_size(from: RawFd, to: RawFd) -> Option<(u16, u16)> {
+    unsafe {
+        let mut size: winsize = MaybeUninit::zeroed().assume_init();
+        if ioctl(from, TIOCGWINSZ, &mut size) != 0 || ioctl(to, TIOCSWINSZ, &size) != 0 {
+            return None;
+        }
+        Some((size.ws_row, size.ws_col))
+    }
+}
+
+/// How a supervised command ended
+pub struct CommandExit {
+    pub status: ExitStatus,
+    pub timed_out: bool, // The command was stopped because it ran past its timeout
+}
+
+impl CommandExit {
+    /// Exit code for rudo to return: TIMEOUT_EXIT_CODE if the command timed out, else
+    /// its own exit code, or 128 + the signal number if it was killed like shells do
+    pub fn code(&self) -> i32 {
+        if self.timed_out {
+            return TIMEOUT_EXIT_CODE;
+        }
+        match (self.status.code(), self.status.signal()) {
+            (Some(code), _) => code,
+            (None, Some(sig)) => 128 + sig,
+            _ => 1,
+        }
+    }
+}
+
+/// Stops a command's process group once it runs past its timeout
+struct Timeout {
+    deadline: Instant,   // When the next of TIMEOUT_SIGNALS is sent
+    signals_sent: usize,
+}
+
+impl Timeout {
+    fn new(timeout: Duration) -> Timeout {
+        Timeout { deadline: Instant::now() + timeout, signals_sent: 0 }
+    }
+
+    /// Send the next signal if its time has come
+    fn check(&mut self, pgid: pid_t) {
+        if self.signals_sent == TIMEOUT_SIGNALS.len() || Instant::now() < self.deadline {
+            return;
+        }
+        // Continue the command in case it's stopped, so it gets to handle SIGTERM
+        unsafe {
+            kill(-pgid, TIMEOUT_SIGNALS[self.signals_sent]);
+            kill(-pgid, SIGCONT);
+        }
+        self.signals_sent += 1;
+        self.deadline = Instant::now() + Duration::from_secs(TIMEOUT_GRACE_SEC);
+    }
+
+    fn expired(&self) -> bool {
+        self.signals_sent > 0
+    }
+}
+
+
+/// Duplicate fd into a new file that's closed on exec
+fn dup_cloexec(fd: RawFd) -> Result<File, Box<dyn Error>> {
+    let dup = unsafe { fcntl(fd, F_DUPFD_CLOEXEC, 3) };
+    if dup < 0 {
+        return Err(Box::new(io::Error::last_os_error()));
+    }
+    Ok(unsafe { File::from_raw_fd(dup) })
+}
+
+/// Create a pipe whose ends are closed on exec, returning its read and write ends
+fn create_pipe() -> Result<(File, File), Box<dyn Error>> {
+    let mut fds: [c_int; 2] = [-1, -1];
+    if unsafe { pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(Box::new(io::Error::last_os_error()));
+    }
+    // pipe2() isn't available everywhere, so the flag is set afterwards
+    let (read_end, write_end) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+    set_cloexec(read_end.as_raw_fd())?;
+    set_cloexec(write_end.as_raw_fd())?;
+    Ok((read_end, write_end))
+}
+
+/// Data that rudo passes on in one direction, recorded as the given stream.
+/// Writes never block, so that a full pipe in one direction can't stop the others. Data that
+/// can't be written yet is kept, and nothing more is read until it has been passed on.
+struct Channel {
+    from: File,
+    to: File,
+    stream: Stream,
+    pending: Vec<u8>, // Data that was read but not passed on yet
+    to_flags: c_int,  // File status flags of `to` before it was made non-blocking
+    at_eof: bool,     // Nothing is left to read, so the channel closes once pending is passed on
+}
+
+impl Channel {
+    fn new(from: File, to: File, stream: Stream) -> Result<Channel, Box<dyn Error>> {
+        let to_flags = unsafe { fcntl(to.as_raw_fd(), F_GETFL) };
+        if to_flags < 0 || unsafe { fcntl(to.as_raw_fd(), F_SETFL, to_flags | O_NONBLOCK) } < 0 {
+            return Err(Box::new(io::Error::last_os_error()));
+        }
+        Ok(Channel { from, to, stream, pending: Vec::new(), to_flags, at_eof: false })
+    }
+
+    /// Whether data flows from the command to the user
+    fn is_output(&self) -> bool {
+        self.stream != Stream::Stdin && self.stream != Stream::TtyIn
+    }
+
+    /// Whether everything has been read and passed on
+    fn is_done(&self) -> bool {
+        self.at_eof && self.pending.is_empty()
+    }
+
+    /// Write as much of the pending data as possible. Returns false if it can't be passed on.
+    fn flush(&mut self) -> bool {
+        while !self.pending.is_empty() {
+            match self.to.write(&self.pending) {
+                Ok(0) => return false,
+                Ok(n) => { self.pending.drain(..n); },
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(_) => return false,
+            }
+        }
+        true
+    }
+
+    /// Record data that was read and pass it on. Returns false if it can't be passed on.
+    fn pass_on(&mut self, data: &[u8], iolog: &mut Option<&mut IoLogWriter>) -> Result<bool, Box<dyn Error>> {
+        if let Some(ref mut log) = *iolog {
+            log.write(self.stream, data)?;
+        }
+        self.pending.extend_from_slice(data);
+        Ok(self.flush())
+    }
+
+    /// Pass on whatever can be read.
+    /// Returns false once everything has been passed on or the data can't be passed on.
+    fn relay(&mut self, buf: &mut [u8], iolog: &mut Option<&mut IoLogWriter>) -> Result<bool, Box<dyn Error>> {
+        match self.from.read(buf) {
+            Ok(0) => self.at_eof = true,
+            Ok(n) => if !self.pass_on(&buf[..n], iolog)? { return Ok(false) },
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted || e.kind() == io::ErrorKind::WouldBlock => {},
+            // EIO means every writer has closed the pty
+            Err(_) => self.at_eof = true,
+        }
+        Ok(!self.is_done())
+    }
+
+    /// Pass on what's pending and what's left to read without waiting for more.
+    /// Writes block from here on, so all of it gets through.
+    fn drain(&mut self, buf: &mut [u8], iolog: &mut Option<&mut IoLogWriter>) -> Result<(), Box<dyn Error>> {
+        unsafe {
+            fcntl(self.from.as_raw_fd(), F_SETFL, O_NONBLOCK);
+            fcntl(self.to.as_raw_fd(), F_SETFL, self.to_flags);
+        }
+        if !self.flush() {
+            return Ok(());
+        }
+        loop {
+            match self.from.read(buf) {
+                Ok(n) if n > 0 => if !self.pass_on(&buf[..n], iolog)? { break },
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+                _ => break,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Channel {
+    /// `to` may share its flags with the invoking shell's own streams, so put them back
+    fn drop(&mut self) {
+        unsafe { fcntl(self.to.as_raw_fd(), F_SETFL, self.to_flags); }
+    }
+}
+
+/// Run the command as a child of rudo, forward the signals rudo receives to it and return
+/// its status once it's done.
+///
+/// If use_pty or a timeout is set and rudo has a terminal, the command gets a new pty as its
+/// controlling terminal, in place of the user's terminal. Its standard streams that are connected to the
+/// user's terminal are connected to the pty instead, and rudo relays I/O and window size
+/// changes between the two. Otherwise the command runs on the user's terminal, and rudo
+/// only waits for it.
+///
+/// If iolog is given, everything passed between the user and the command is recorded in it.
+/// Standard streams that aren't connected to the terminal are then passed through pipes
+/// so they can be recorded too.
+///
+/// If the command runs for longer than timeout, its whole process group is sent SIGTERM, and
+/// SIGKILL if it's still around after TIMEOUT_GRACE_SEC. It needs a process group of its own
+/// for that, which only a pty can give it without taking the user's terminal away from rudo.
+pub fn supervise(command: &mut Command, use_pty: bool, mut iolog: Option<&mut IoLogWriter>,
+                 timeout: Option<Duration>) -> Result<CommandExit, Box<dyn Error>> {
+    let tty = if use_pty || timeout.is_some() { get_tty().ok() } else { None };
+
+    let mut channels = Vec::new();
+    let mut pty = None;
+    if let Some(ref tty) = tty {
+        let tty_fd = tty.as_raw_fd();
+        let mut saved = MaybeUninit::<termios>::uninit();
+        if unsafe { tcgetattr(tty_fd, saved.as_mut_ptr()) } != 0 {
+            return Err(Box::new(io::Error::last_os_error()));
+        }
+        let saved = unsafe { saved.assume_init() };
+
+        // Create the pty with the same mode and size as the user's terminal
+        let (master, slave) = unsafe {
+            let mut size: winsize = MaybeUninit::zeroed().assume_init();
+            ioctl(tty_fd, TIOCGWINSZ, &mut size);
+
+            // openpty() takes mutable pointers on some platforms, so it gets a copy of the mode
+            let mut mode = saved;
+            let mut master_fd: c_int = -1;
+            let mut slave_fd: c_int = -1;
+            let (mode, size) = (ptr::addr_of_mut!(mode), ptr::addr_of_mut!(size));
+            if openpty(&mut master_fd, &mut slave_fd, ptr::null_mut(), mode, size) != 0 {
+                return Err(Box::new(io::Error::last_os_error()));
+            }
+            (File::from_raw_fd(master_fd), File::from_raw_fd(slave_fd))
+        };
+        set_cloexec(master.as_raw_fd())?;
+        set_cloexec(slave.as_raw_fd())?;
+
+        channels.push(Some(Channel::new(master.try_clone()?, tty.try_clone()?, Stream::TtyOut)?));
+        if unsafe { libc::isatty(0) } == 1 {
+            channels.push(Some(Channel::new(tty.try_clone()?, master.try_clone()?, Stream::TtyIn)?));
+        }
+        pty = Some((master, slave, saved));
+    }
+
+    // Connect the streams that were on the user's terminal to the pty. Unless they're
+    // recorded, the other streams are left alone.
+    let slave_fd = pty.as_ref().map(|(_, slave, _)| slave.as_raw_fd());
+    for fd in 0..3 {
+        let stdio = if let (Some(slave_fd), 1) = (slave_fd, unsafe { libc::isatty(fd) }) {
+            Stdio::from(dup_cloexec(slave_fd)?)
+        } else if iolog.is_some() {
+            let (read_end, write_end) = create_pipe()?;
+            let user_end = dup_cloexec(fd)?;
+            match fd {
+                0 => {
+                    channels.push(Some(Channel::new(user_end, write_end, Stream::Stdin)?));
+                    Stdio::from(read_end)
+                },
+                1 => {
+                    channels.push(Some(Channel::new(read_end, user_end, Stream::Stdout)?));
+                    Stdio::from(write_end)
+                },
+                _ => {
+                    channels.push(Some(Channel::new(read_end, user_end, Stream::Stderr)?));
+                    Stdio::from(write_end)
+                },
+            }
+        } else {
+            continue;
+        };
+        match fd {
+            0 => command.stdin(stdio),
+            1 => command.stdout(stdio),
+            _ => command.stderr(stdio),
+        };
+    }
+
+    // Make the pty the controlling terminal of the command's new session
+    if let Some(slave_fd) = slave_fd {
+        unsafe {
+            command.pre_exec(move || {
+                if libc::setsid() < 0 || ioctl(slave_fd, TIOCSCTTY as _, 0) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+    } else if timeout.is_some() {
+        // There's no terminal to share, so the command can lead its own process group
+        command.process_group(0);
+    }
+    let mut timeout = timeout.map(Timeout::new);
+
+    let _signals = SignalGuard::new(pty.is_some());
+    let child = command.spawn()?;
+    let pid = child.id() as pid_t;
+    CHILD_PID.store(pid, Ordering::SeqCst);
+
+    // Close rudo's copies of the command's ends of the pty and pipes
+    command.stdin(Stdio::inherit()).stdout(Stdio::inherit()).stderr(Stdio::inherit());
+
+    let res = match (tty, pty) {
+        (Some(tty), Some((master, slave, saved))) => {
+            drop(slave);
+            let raw_mode = RawModeGuard::new(tty.as_raw_fd(), saved)?;
+            let terminal = Some((&tty, &master, &raw_mode));
+            relay(&mut channels, terminal, &mut iolog, &mut timeout, pid)
+        },
+        _ => relay(&mut channels, None, &mut iolog, &mut timeout, pid),
+    };
+    CHILD_PID.store(0, Ordering::SeqCst);
+    let status = res?;
+    Ok(CommandExit { status, timed_out: timeout.is_some_and(|t| t.expired()) })
+}
+
+/// The user's terminal and the pty, along with the guard that keeps the terminal in raw mode
+type Terminal<'a> = (&'a File, &'a File, &'a RawModeGuard);
+
+/// Relay I/O through the channels until the command exits
+fn relay(channels: &mut [Option<Channel>], terminal: Option<Terminal>, iolog: &mut Option<&mut IoLogWriter>,
+         timeout: &mut Option<Timeout>, pid: pid_t) -> Result<ExitStatus, Box<dyn Error>> {
+    let mut buf = [0u8; 4096];
+
+    loop {
+        if let Some(ref mut timeout) = *timeout {
+            timeout.check(pid);
+        }
+        if WINDOW_CHANGED.swap(false, Ordering::SeqCst) {
+            if let Some((tty, master, _)) = terminal {
+                if let (Some((rows, cols)), Some(ref mut log)) =
+                    (copy_window_size(tty.as_raw_fd(), master.as_raw_fd()), iolog.as_mut()) {
+                    log.window_changed(rows, cols)?;
+                }
+            }
+        }
+
+        // Check on the command without blocking
+        let mut status: c_int = 0;
+        let res = unsafe { waitpid(pid, &mut status, WNOHANG | WUNTRACED) };
+        if res == pid {
+            if libc::WIFSTOPPED(status) {
+                // The command was suspended, so suspend rudo too and give the user their
+                // terminal back. Once rudo is continued, continue the command.
+                if let Some((_, _, raw_mode)) = terminal {
+                    raw_mode.leave();
+                }
+                unsafe { kill(libc::getpid(), SIGSTOP); }
+                if let Some((tty, master, raw_mode)) = terminal {
+                    raw_mode.enter()?;
+                    copy_window_size(tty.as_raw_fd(), master.as_raw_fd());
+                }
+                unsafe { kill(pid, SIGCONT); }
+                continue;
+            }
+
+            // Pass on whatever output is left, then return the command's result. Anything
+            // the command started in the background may still hold the other ends, so
+            // only read what's already there.
+            for channel in channels.iter_mut().flatten() {
+                if channel.is_output() {
+                    channel.drain(&mut buf, iolog)?;
+                }
+            }
+            return Ok(ExitStatus::from_raw(status));
+        } else if res < 0 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() != Some(EINTR) {
+                return Err(Box::new(err));
+            }
+        }
+
+        // Wait for data to read, or for room to pass on what couldn't be written yet
+        let mut fds: Vec<pollfd> = channels.iter().map(|c| match *c {
+            Some(ref c) if !c.pending.is_empty() => pollfd { fd: c.to.as_raw_fd(), events: POLLOUT, revents: 0 },
+            Some(ref c) => pollfd { fd: c.from.as_raw_fd(), events: POLLIN, revents: 0 },
+            None => pollfd { fd: -1, events: 0, revents: 0 },
+        }).collect();
+        let res = unsafe { poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, POLL_INTERVAL_MS) };
+        if res < 0 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() == Some(EINTR) {
+                continue;
+            }
+            return Err(Box::new(err));
+        }
+
+        for (slot, fd) in channels.iter_mut().zip(fds.iter()) {
+            if fd.revents == 0 {
+                continue;
+            }
+            // Dropping a channel closes its ends, so the command sees the end of
+            // its input and rudo stops polling the output
+            let open = match *slot {
+                Some(ref mut channel) if !channel.pending.is_empty() => channel.flush() && !channel.is_done(),
+                Some(ref mut channel) => channel.relay(&mut buf, iolog)?,
+                None => continue,
+            };
+            if !open {
+                *slot = None;
+            }
+        }
+    }
+}
@@ -0,0 +1,204 @@
+//!
+//! Session store keeping one JSON file per session in a root-only directory
+//!
+
+use std::io;
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::fs::DirBuilder;
+use std::error::Error;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+
+use serde_json;
+use zeroize::Zeroizing;
+use libc::{flock, LOCK_EX};
+
+use session::Session;
+use session::SessionStore;
+use session::SessionUpdate;
+use keyfile;
+
+/// Path of a session file and its contents, if they could be parsed
+type SessionFile = (PathBuf, Option<Session>);
+
+/// Stores every session in its own file under `<root>/<username>/`
+pub struct FileSessionStore {
+    root: &'static str,     // Directory holding a subdirectory for every user
+    key_path: &'static str, // File holding the key that session records are authenticated with
+}
+
+/// Make sure the directory at path is owned by root and only accessible by root,
+/// creating it if it doesn't exist. Symlinks are never followed.
+fn init_root_dir(path: &Path) -> Result<(), Box<dyn Error>> {
+    match DirBuilder::new().mode(0o700).create(path) {
+        Ok(_) => {},
+        Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {},
+        Err(e) => return Err(Box::new(e)),
+    }
+
+    let metadata = fs::symlink_metadata(path)?;
+    if !metadata.file_type().is_dir() || metadata.uid() != 0 {
+        return Err(From::from(format!("{} must be a directory owned by root!", path.display())));
+    }
+    if metadata.mode() & 0o777 != 0o700 {
+        // Older versions created the directories without the execute bit, so fix the mode
+        fs::set_permissions(path, fs::Permissions::from_mode(0o700))?;
+    }
+    Ok(())
+}
+
+/// Whether a session file could have been created by rudo
+fn is_trusted_file(metadata: &fs::Metadata) -> bool {
+    metadata.file_type().is_file() && metadata.uid() == 0 && metadata.mode() & 0o077 == 0
+        && metadata.nlink() == 1
+}
+
+impl FileSessionStore {
+    pub fn new(root: &'static str, key_path: &'static str) -> FileSessionStore {
+        FileSessionStore { root, key_path }
+    }
+
+    /// Verify every entry under the root. Each entry has to be a root-only user directory,
+    /// and session files that rudo couldn't have created are removed.
+    fn verify_tree(&self) -> Result<(), Box<dyn Error>> {
+        for user_dir in fs::read_dir(self.root)? {
+            let user_dir = user_dir?.path();
+            init_root_dir(&user_dir)?;
+
+            for file in fs::read_dir(&user_dir)? {
+                let file = file?.path();
+                if !is_trusted_file(&fs::symlink_metadata(&file)?) {
+                    fs::remove_file(&file).map_err(|_| format!("Unexpected entry {} in session directory!",
+                                                                file.display()))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Initalize the root and the user's subdirectory if they don't exist, verify
+    /// everything in them and take an exclusive lock on the user's subdirectory.
+    /// The lock is held until the returned file is dropped.
+    fn lock_user_dir(&self, username: &str) -> Result<File, Box<dyn Error>> {
+        if username.is_empty() || username.contains('/') || username.starts_with('.') {
+            return Err(From::from("Invalid username!"));
+        }
+
+        init_root_dir(Path::new(self.root))?;
+        let user_sub_path_str = format!("{}/{}", self.root, username);
+        init_root_dir(Path::new(&user_sub_path_str))?;
+        self.verify_tree()?;
+
+        let dir = OpenOptions::new().read(true)
+            .custom_flags(libc::O_DIRECTORY | libc::O_NOFOLLOW).open(&user_sub_path_str)?;
+        if unsafe { flock(dir.as_raw_fd(), LOCK_EX) } != 0 {
+            return Err(Box::new(io::Error::last_os_error()));
+        }
+        Ok(dir)
+    }
+
+    /// Read all of the user's session files. The caller has to hold the lock.
+    fn read_user_sessions(&self, username: &str) -> Result<Vec<SessionFile>, Box<dyn Error>> {
+        let user_sub_path_str = format!("{}/{}", self.root, username);
+        let mut sessions = Vec::new();
+
+        for file in fs::read_dir(&user_sub_path_str)? {
+            let path = file?.path();
+            let mut f = OpenOptions::new().read(true).custom_flags(libc::O_NOFOLLOW).open(&path)?;
+            if !is_trusted_file(&f.metadata()?) {
+                return Err(From::from(format!("Unexpected entry {} in session directory!", path.display())));
+            }
+
+            let mut buf = String::new();
+            f.read_to_string(&mut buf)?;
+            sessions.push((path, serde_json::from_str::<Session>(&buf).ok()));
+        }
+
+        Ok(sessions)
+    }
+
+    /// Write a session to a new file. The caller has to hold the lock.
+    fn write_user_session(&self, username: &str, session: &Session) -> Result<(), Box<dyn Error>> {
+        let session_str = serde_json::to_string(session)?;
+
+        // Use the lowest number that's not taken as the filename. The file is created
+        // exclusively with its final mode, so an existing file or symlink is never reused.
+        let mut i = 0;
+        loop {
+            let cur_filename = format!("{}/{}/{}", self.root, username, i);
+            match OpenOptions::new().write(true).create_new(true).mode(0o600)
+                .custom_flags(libc::O_NOFOLLOW).open(&cur_filename) {
+                Ok(mut f) => {
+                    f.write_all(session_str.as_bytes())?;
+                    return Ok(());
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => i += 1,
+                Err(e) => return Err(Box::new(e)),
+            }
+        }
+    }
+}
+
+impl SessionStore for FileSessionStore {
+    fn get_key(&self) -> Result<Zeroizing<Vec<u8>>, Box<dyn Error>> {
+        keyfile::get_or_create_key(self.key_path)
+    }
+
+    fn update(&self, username: &str, f: &mut SessionUpdate) -> Result<(), Box<dyn Error>> {
+        let _lock = self.lock_user_dir(username)?;
+
+        // Unreadable sessions can't be valid anyway, so remove them right away
+        let mut files = Vec::new();
+        let mut sessions = Vec::new();
+        for (path, session) in self.read_user_sessions(username)? {
+            match session {
+                Some(s) => {
+                    files.push((path, s.mac.clone()));
+                    sessions.push(s);
+                },
+                None => fs::remove_file(&path)?,
+            }
+        }
+
+        f(&mut sessions)?;
+
+        // Sessions are identified by their MAC. Remove the files of the sessions that
+        // were dropped, then write the ones that were added.
+        for (path, mac) in files.iter() {
+            if !sessions.iter().any(|s| s.mac == *mac) {
+                fs::remove_file(path)?;
+            }
+        }
+        for session in sessions.iter() {
+            if !files.iter().any(|(_, mac)| *mac == session.mac) {
+                self.write_user_session(username, session)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn list_users(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        if !Path::new(self.root).exists() {
+            return Ok(Vec::new());
+        }
+        init_root_dir(Path::new(self.root))?;
+        self.verify_tree()?;
+
+        let mut users = Vec::new();
+        for user_dir in fs::read_dir(self.root)? {
+            users.push(user_dir?.file_name().to_string_lossy().into_owned());
+        }
+        users.sort();
+        Ok(users)
+    }
+}
@@ -0,0 +1,632 @@
+//!
+//! Support for user sessions
+//!
+
+use std::io;
+use std::fs;
+use std::ffi::CStr;
+use std::error::Error;
+use std::os::unix::fs::MetadataExt;
+
+use time;
+use hex;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use zeroize::Zeroizing;
+use libc::{isatty, ttyname, getppid, clock_gettime, timespec};
+#[cfg(target_os = "linux")]
+use libc::CLOCK_BOOTTIME as BOOT_CLOCK;
+// The monotonic clock keeps counting while the system sleeps on macOS
+#[cfg(not(target_os = "linux"))]
+use libc::CLOCK_MONOTONIC as BOOT_CLOCK;
+
+use settings::Settings;
+use settings::SessionScope;
+use settings::SessionStoreType;
+
+mod file;
+#[cfg(target_os = "linux")]
+mod tmpfs;
+use self::file::FileSessionStore;
+#[cfg(target_os = "linux")]
+use self::tmpfs::TmpfsSessionStore;
+
+use SESSION_PATH;
+use SESSION_KEY_PATH;
+
+// Value of /proc/self/sessionid for processes outside of any login session
+static NO_AUDIT_SESSION: &str = "4294967295";
+
+/// Struct defining a single user session
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Session {
+    scope_id: String,      // What the session is bound to, see get_scope_id()
+    boot_id: String,       // Kernel boot id the session was created during
+    start_boottime: i64,   // Seconds since boot that session was started at, see get_boottime()
+    end_boottime: i64,     // Seconds since boot that session should expire at
+    auth_user: String,     // Account whose credentials were presented to start the session
+    target_user: String,   // Account that commands are run as
+    tag: Option<String>,   // Tag of the rule the session was started for, if it had one
+    mac: String,           // Hex HMAC-SHA256 of the other fields and the username, see session_mac()
+}
+
+/// Modification of a user's sessions, see `SessionStore::update()`
+pub type SessionUpdate<'a> = dyn FnMut(&mut Vec<Session>) -> Result<(), Box<dyn Error>> + 'a;
+
+/// Storage for session records. Stores only keep the records, validating them is up to the caller.
+pub trait SessionStore {
+    /// Get the key used to authenticate session records, generating it on first use.
+    /// Only root may be able to read it, so users can't forge or alter their sessions.
+    fn get_key(&self) -> Result<Zeroizing<Vec<u8>>, Box<dyn Error>>;
+
+    /// Load all of the user's sessions and let `f` modify them, then store the result.
+    /// Concurrent updates of the same user are serialized. Records that can't be
+    /// read are dropped.
+    fn update(&self, username: &str, f: &mut SessionUpdate) -> Result<(), Box<dyn Error>>;
+
+    /// Get the names of all users that have stored sessions
+    fn list_users(&self) -> Result<Vec<String>, Box<dyn Error>>;
+}
+
+/// Description of an active session, for displaying to the user
+pub struct SessionInfo {
+    pub username: String,
+    pub id: String,           // Short id that identifies the session for --revoke-session
+    pub scope: String,        // What the session is bound to, in a readable form
+    pub auth_user: String,
+    pub target_user: String,
+    pub tag: Option<String>,
+    pub start_timestamp: i64, // UNIX Timestamp that the session was started at
+    pub end_timestamp: i64,   // UNIX Timestamp that the session expires at
+    pub current: bool,        // Whether the session is in the scope of this invocation
+}
+
+// Number of hex digits of a session's MAC used as its id
+const SESSION_ID_LEN: usize = 8;
+
+/// Get the session store selected in the settings
+fn get_store(settings: &Settings) -> Result<Box<dyn SessionStore>, Box<dyn Error>> {
+    Ok(match settings.session_store {
+        SessionStoreType::File => Box::new(FileSessionStore::new(SESSION_PATH, SESSION_KEY_PATH)),
+        #[cfg(target_os = "linux")]
+        SessionStoreType::Tmpfs => Box::new(TmpfsSessionStore::new()?),
+        // Rejected by Settings::validate, since only Linux can tell whether a mount is tmpfs
+        #[cfg(not(target_os = "linux"))]
+        SessionStoreType::Tmpfs => return Err(From::from("The tmpfs session store is only available on Linux!")),
+    })
+}
+
+/// Safe wrapper to get the name of the current ttyname
+/// and return as a Rust string. Stdin, stdout and stderr are checked in that order,
+/// since stdin is redirected when the password is piped in.
+/// Returns None if none of them is connected to a TTY.
+pub fn get_cur_tty_name() -> Result<Option<String>, Box<dyn Error>> {
+    unsafe {
+        for fd in 0..3 {
+            if isatty(fd) == 0 {
+                continue;
+            }
+            let ttyname_c = ttyname(fd);
+            // Verify that call didn't fail
+            if ttyname_c.is_null() {
+                return Err(From::from("ttyname() call failed!"));
+            }
+            let ttyname_rust = CStr::from_ptr(ttyname_c).to_string_lossy().into_owned();
+            return Ok(Some(ttyname_rust));
+        }
+        Ok(None)
+    }
+}
+
+/// Start time of the given process in clock ticks since boot, from /proc/<pid>/stat
+fn get_process_start_time(pid: i32) -> Result<u64, Box<dyn Error>> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid))?;
+
+    // The command name is in parentheses and may itself contain spaces or parentheses,
+    // so the remaining fields start after the last ')'. starttime is field 22 overall.
+    let rest = match stat.rfind(')') {
+        Some(i) => &stat[i + 1..],
+        None => return Err(From::from("Unable to parse process stat!")),
+    };
+    let start_time = rest.split_whitespace().nth(19)
+        .ok_or("Unable to parse process stat!")?;
+    Ok(start_time.parse()?)
+}
+
+/// Identify what the current invocation's session is bound to for the given scope.
+/// Returns None if there is nothing to bind to, in which case there are no sessions.
+fn get_scope_id(scope: SessionScope) -> Result<Option<String>, Box<dyn Error>> {
+    match scope {
+        SessionScope::Tty => {
+            let ttyname = match get_cur_tty_name()? {
+                Some(v) => v,
+                None => return Ok(None),
+            };
+            // Pseudo terminals are recreated for every login, so the change time tells
+            // a reused TTY name apart from the one the session was created on
+            let metadata = fs::metadata(&ttyname)?;
+            Ok(Some(format!("tty:{}:{}.{}", ttyname, metadata.ctime(), metadata.ctime_nsec())))
+        },
+        SessionScope::Ppid => {
+            // The start time tells a reused process id apart from the original process
+            let ppid = unsafe { getppid() };
+            let start_time = get_process_start_time(ppid)?;
+            Ok(Some(format!("ppid:{}:{}", ppid, start_time)))
+        },
+        SessionScope::Login => {
+            let session_id = fs::read_to_string("/proc/self/sessionid")?;
+            let session_id = session_id.trim();
+            if session_id == NO_AUDIT_SESSION {
+                return Ok(None);
+            }
+            Ok(Some(format!("login:{}", session_id)))
+        },
+        SessionScope::Global => Ok(Some(String::from("global"))),
+    }
+}
+
+/// Random id the kernel assigns at every boot, so sessions don't survive a reboot
+#[cfg(target_os = "linux")]
+fn get_boot_id() -> Result<String, Box<dyn Error>> {
+    Ok(fs::read_to_string("/proc/sys/kernel/random/boot_id")?.trim().to_string())
+}
+
+/// Time the system was booted at, so sessions don't survive a reboot
+#[cfg(not(target_os = "linux"))]
+fn get_boot_id() -> Result<String, Box<dyn Error>> {
+    let mut mib = [libc::CTL_KERN, libc::KERN_BOOTTIME];
+    let mut boottime = libc::timeval { tv_sec: 0, tv_usec: 0 };
+    let mut len = std::mem::size_of::<libc::timeval>();
+    let res = unsafe {
+        libc::sysctl(mib.as_mut_ptr(), 2, &mut boottime as *mut _ as *mut libc::c_void, &mut len,
+            std::ptr::null_mut(), 0)
+    };
+    if res != 0 {
+        return Err(Box::new(io::Error::last_os_error()));
+    }
+    Ok(format!("{}.{:06}", boottime.tv_sec, boottime.tv_usec))
+}
+
+/// Seconds since boot, including time spent suspended. Unlike the wall clock
+/// this can't be set back to extend a session.
+fn get_boottime() -> Result<i64, Box<dyn Error>> {
+    let mut ts = timespec { tv_sec: 0, tv_nsec: 0 };
+    if unsafe { clock_gettime(BOOT_CLOCK, &mut ts) } != 0 {
+        return Err(Box::new(io::Error::last_os_error()));
+    }
+    Ok(ts.tv_sec)
+}
+
+/// MAC over every field of the session except the MAC itself. The username is included
+/// so a session can't be copied into another user's directory.
+fn session_mac(key: &[u8], username: &str, session: &Session) -> Result<Hmac<Sha256>, Box<dyn Error>> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key)
+        .map_err(|_| "Invalid session key length!")?;
+    // Every field is terminated with a NUL so field boundaries can't be shifted
+    for field in [username, &session.scope_id, &session.boot_id, &session.auth_user, &session.target_user].iter() {
+        mac.update(field.as_bytes());
+        mac.update(b"\0");
+    }
+    // Tags can't be empty, so an empty string stands for a missing tag
+    mac.update(session.tag.as_deref().unwrap_or("").as_bytes());
+    mac.update(b"\0");
+    mac.update(&session.start_boottime.to_be_bytes());
+    mac.update(&session.end_boottime.to_be_bytes());
+    Ok(mac)
+}
+
+/// Whether the session was created by rudo for this user during the current boot and hasn't expired
+fn is_session_valid(key: &[u8], username: &str, session: &Session, boot_id: &str, now: i64) -> bool {
+    let tag = match hex::decode(&session.mac) {
+        Ok(v) => v,
+        Err(_) => return false,
+    };
+    let mac_ok = match session_mac(key, username, session) {
+        Ok(mac) => mac.verify_slice(&tag).is_ok(),
+        Err(_) => false,
+    };
+
+    mac_ok && session.boot_id == boot_id && session.start_boottime <= now && now < session.end_boottime
+}
+
+/// Describe a scope id in a readable form, leaving out the parts that only tell reused ids apart
+fn describe_scope(scope_id: &str) -> String {
+    let parts: Vec<&str> = scope_id.splitn(2, ':').collect();
+    match (parts[0], parts.get(1)) {
+        ("tty", Some(rest)) => rest.rsplit_once(':').map_or(*rest, |(tty, _)| tty).to_string(),
+        ("ppid", Some(rest)) => format!("pid {}", rest.split(':').next().unwrap_or("")),
+        ("login", Some(rest)) => format!("login session {}", rest),
+        _ => scope_id.to_string(),
+    }
+}
+
+/// Get the user's sessions that are still valid, removing all others from the store
+fn get_valid_sessions(store: &dyn SessionStore, username: &str) -> Result<Vec<Session>, Box<dyn Error>> {
+    let key = store.get_key()?;
+    let boot_id = get_boot_id()?;
+    let now = get_boottime()?;
+
+    let mut valid = Vec::new();
+    store.update(username, &mut |sessions| {
+        sessions.retain(|s| is_session_valid(&key, username, s, &boot_id, now));
+        valid = sessions.clone();
+        Ok(())
+    })?;
+    Ok(valid)
+}
+
+/// Get the active sessions of the given user, or of every user if there is none.
+/// Expired and invalid sessions are removed along the way.
+pub fn list_sessions(settings: &Settings, username: Option<&str>) -> Result<Vec<SessionInfo>, Box<dyn Error>> {
+    let store = get_store(settings)?;
+    // Only look at users that have sessions, so no empty directories are created for others
+    let usernames: Vec<String> = store.list_users()?.into_iter()
+        .filter(|u| username.is_none_or(|name| name == u)).collect();
+    let current_scope_id = get_scope_id(settings.session_scope)?;
+
+    // Sessions are timed with the boot clock, so convert them to wall clock times for display
+    let now = get_boottime()?;
+    let cur_timestamp = time::OffsetDateTime::now_utc().unix_timestamp();
+
+    let mut infos = Vec::new();
+    for username in usernames {
+        for session in get_valid_sessions(store.as_ref(), &username)? {
+            infos.push(SessionInfo {
+                username: username.clone(),
+                id: session.mac[..SESSION_ID_LEN].to_string(),
+                scope: describe_scope(&session.scope_id),
+                auth_user: session.auth_user.clone(),
+                target_user: session.target_user.clone(),
+                tag: session.tag.clone(),
+                start_timestamp: cur_timestamp - (now - session.start_boottime),
+                end_timestamp: cur_timestamp + (session.end_boottime - now),
+                current: current_scope_id.as_ref() == Some(&session.scope_id),
+            });
+        }
+    }
+    Ok(infos)
+}
+
+/// Remove the user's session with the given id, or all of their sessions if there is no id.
+/// Returns the number of sessions removed.
+pub fn revoke_sessions(settings: &Settings, username: &str, id: Option<&str>) -> Result<usize, Box<dyn Error>> {
+    remove_sessions_by_id(get_store(settings)?.as_ref(), username, id)
+}
+
+/// Remove the user's session with the given id from the store, or all of their sessions if there is no id
+fn remove_sessions_by_id(store: &dyn SessionStore, username: &str, id: Option<&str>) -> Result<usize, Box<dyn Error>> {
+    if id.is_some_and(|id| id.len() != SESSION_ID_LEN) {
+        return Err(From::from(format!("Session ids are {} characters long!", SESSION_ID_LEN)));
+    }
+
+    let mut removed = 0;
+    store.update(username, &mut |sessions| {
+        let before = sessions.len();
+        sessions.retain(|s| id.is_some_and(|id| s.mac.get(..SESSION_ID_LEN) != Some(id)));
+        removed = before - sessions.len();
+        Ok(())
+    })?;
+    Ok(removed)
+}
+
+/// Create a new signed session for the user that will last for the given time in seconds
+fn new_session(key: &[u8], username: &str, scope_id: String, auth_user: &str, target_user: &str,
+               tag: Option<&str>, time: i64) -> Result<Session, Box<dyn Error>> {
+    let now = get_boottime()?;
+    let mut session = Session {
+        scope_id,
+        boot_id: get_boot_id()?,
+        start_boottime: now,
+        end_boottime: now + time,
+        auth_user: auth_user.to_string(),
+        target_user: target_user.to_string(),
+        tag: tag.map(String::from),
+        mac: String::new(),
+    };
+    session.mac = hex::encode(session_mac(key, username, &session)?.finalize().into_bytes());
+    Ok(session)
+}
+
+/// Whether the session was authenticated as auth_user and covers commands run as target_user
+/// by rules with the given tag
+fn session_covers(session: &Session, auth_user: &str, target_user: &str, tag: Option<&str>) -> bool {
+    session.auth_user == auth_user && session.target_user == target_user && session.tag.as_deref() == tag
+}
+
+/// Whether the user has a valid session in the store that's bound to scope_id and covers
+/// auth_user, target_user and tag. Invalid sessions are removed along the way.
+fn find_session(store: &dyn SessionStore, username: &str, scope_id: &str, auth_user: &str, target_user: &str,
+                tag: Option<&str>) -> Result<bool, Box<dyn Error>> {
+    let sessions = get_valid_sessions(store, username)?;
+    Ok(sessions.iter().any(|s| s.scope_id == scope_id && session_covers(s, auth_user, target_user, tag)))
+}
+
+/// Add a new session to the store. If replace is set, the user's existing sessions in its scope
+/// that cover the same users and tag are removed.
+fn add_session(store: &dyn SessionStore, username: &str, session: Session, replace: bool)
+    -> Result<(), Box<dyn Error>> {
+    let mut session = Some(session);
+    store.update(username, &mut |sessions| {
+        if let Some(new) = session.take() {
+            if replace {
+                sessions.retain(|s| s.scope_id != new.scope_id
+                    || !session_covers(s, &new.auth_user, &new.target_user, new.tag.as_deref()));
+            }
+            sessions.push(new);
+        }
+        Ok(())
+    })
+}
+
+/// Remove the user's sessions bound to scope_id from the store
+fn remove_scope_sessions(store: &dyn SessionStore, username: &str, scope_id: &str) -> Result<(), Box<dyn Error>> {
+    store.update(username, &mut |sessions| {
+        sessions.retain(|s| s.scope_id != scope_id);
+        Ok(())
+    })
+}
+
+/// Checks to see if the user has an active session in the configured scope authenticated as auth_user,
+/// that covers running commands as target_user with rules of the given tag.
+/// Also deletes all of the user's sessions that have expired, are from a previous boot
+/// or were tampered with. Returns whether the user has an active session or not
+pub fn check_session(settings: &Settings, username: &str, auth_user: &str, target_user: &str, tag: Option<&str>)
+    -> Result<bool, Box<dyn Error>> {
+    // Work out what the session would be bound to. Without that there can't be a session.
+    let scope_id = match get_scope_id(settings.session_scope)? {
+        Some(v) => v,
+        None => return Ok(false),
+    };
+
+    find_session(get_store(settings)?.as_ref(), username, &scope_id, auth_user, target_user, tag)
+}
+
+/// Create a session for the given user in the configured scope, authenticated as auth_user,
+/// for running commands as target_user with rules of the given tag.
+/// It will last for the configured session timeout
+pub fn create_session(settings: &Settings, username: &str, auth_user: &str, target_user: &str, tag: Option<&str>)
+    -> Result<(), Box<dyn Error>> {
+    // Work out what the session is bound to. Without that, no session is created.
+    let scope_id = match get_scope_id(settings.session_scope)? {
+        Some(v) => v,
+        None => return Ok(()),
+    };
+
+    let store = get_store(settings)?;
+    let session = new_session(&store.get_key()?, username, scope_id, auth_user, target_user, tag,
+                              settings.session_timeout_sec)?;
+    add_session(store.as_ref(), username, session, false)
+}
+
+/// Remove the user's sessions in the current scope, so the next command there has to authenticate
+pub fn invalidate_session(settings: &Settings, username: &str) -> Result<(), Box<dyn Error>> {
+    let scope_id = match get_scope_id(settings.session_scope)? {
+        Some(v) => v,
+        None => return Ok(()),
+    };
+    remove_scope_sessions(get_store(settings)?.as_ref(), username, &scope_id)
+}
+
+/// Remove all of the user's sessions, in every scope
+pub fn remove_all_sessions(settings: &Settings, username: &str) -> Result<(), Box<dyn Error>> {
+    get_store(settings)?.update(username, &mut |sessions| {
+        sessions.clear();
+        Ok(())
+    })
+}
+
+/// Restart the user's session in the current scope, authenticated as auth_user, for running
+/// commands as target_user without a rule tag, so that it lasts for the configured session timeout from now
+pub fn extend_session(settings: &Settings, username: &str, auth_user: &str, target_user: &str)
+    -> Result<(), Box<dyn Error>> {
+    let scope_id = match get_scope_id(settings.session_scope)? {
+        Some(v) => v,
+        None => return Ok(()),
+    };
+
+    let store = get_store(settings)?;
+    let session = new_session(&store.get_key()?, username, scope_id, auth_user, target_user, None,
+                              settings.session_timeout_sec)?;
+    add_session(store.as_ref(), username, session, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    static USER: &str = "alice";
+    static SCOPE: &str = "tty:/dev/pts/0:1.0";
+
+    /// Session store that keeps everything in memory
+    struct MemorySessionStore {
+        key: Vec<u8>,
+        sessions: RefCell<HashMap<String, Vec<Session>>>,
+    }
+
+    impl MemorySessionStore {
+        fn new() -> MemorySessionStore {
+            MemorySessionStore { key: vec![0x5a; 32], sessions: RefCell::new(HashMap::new()) }
+        }
+
+        fn sessions(&self, username: &str) -> Vec<Session> {
+            self.sessions.borrow().get(username).cloned().unwrap_or_default()
+        }
+    }
+
+    impl SessionStore for MemorySessionStore {
+        fn get_key(&self) -> Result<Zeroizing<Vec<u8>>, Box<dyn Error>> {
+            Ok(Zeroizing::new(self.key.clone()))
+        }
+
+        fn update(&self, username: &str, f: &mut SessionUpdate) -> Result<(), Box<dyn Error>> {
+            let mut sessions = self.sessions(username);
+            f(&mut sessions)?;
+            self.sessions.borrow_mut().insert(username.to_string(), sessions);
+            Ok(())
+        }
+
+        fn list_users(&self) -> Result<Vec<String>, Box<dyn Error>> {
+            let mut users: Vec<String> = self.sessions.borrow().keys().cloned().collect();
+            users.sort();
+            Ok(users)
+        }
+    }
+
+    fn create(store: &MemorySessionStore, scope_id: &str, target_user: &str, tag: Option<&str>, time: i64) {
+        let session = new_session(&store.key, USER, scope_id.to_string(), USER, target_user, tag, time).unwrap();
+        add_session(store, USER, session, false).unwrap();
+    }
+
+    fn find(store: &MemorySessionStore, username: &str, scope_id: &str, auth_user: &str, target_user: &str,
+            tag: Option<&str>) -> bool {
+        find_session(store, username, scope_id, auth_user, target_user, tag).unwrap()
+    }
+
+    /// Change the user's stored sessions without signing them again
+    fn tamper(store: &MemorySessionStore, f: &dyn Fn(&mut Session)) {
+        store.update(USER, &mut |sessions| {
+            sessions.iter_mut().for_each(f);
+            Ok(())
+        }).unwrap();
+    }
+
+    fn sign(key: &[u8], username: &str, session: &mut Session) {
+        session.mac = hex::encode(session_mac(key, username, session).unwrap().finalize().into_bytes());
+    }
+
+    #[test]
+    fn created_sessions_are_found() {
+        let store = MemorySessionStore::new();
+        assert!(!find(&store, USER, SCOPE, USER, "root", None));
+
+        create(&store, SCOPE, "root", None, 300);
+        assert!(find(&store, USER, SCOPE, USER, "root", None));
+        assert!(!find(&store, USER, "global", USER, "root", None));
+        assert!(!find(&store, "bob", SCOPE, USER, "root", None));
+    }
+
+    #[test]
+    fn invalidate_removes_only_the_current_scope() {
+        let store = MemorySessionStore::new();
+        create(&store, SCOPE, "root", None, 300);
+        create(&store, "global", "root", None, 300);
+
+        remove_scope_sessions(&store, USER, SCOPE).unwrap();
+        assert!(!find(&store, USER, SCOPE, USER, "root", None));
+        assert!(find(&store, USER, "global", USER, "root", None));
+    }
+
+    #[test]
+    fn extend_replaces_the_session() {
+        let store = MemorySessionStore::new();
+        create(&store, SCOPE, "root", None, 10);
+        create(&store, SCOPE, "root", Some("web"), 10);
+
+        let session = new_session(&store.key, USER, SCOPE.to_string(), USER, "root", None, 300).unwrap();
+        add_session(&store, USER, session, true).unwrap();
+
+        let sessions = store.sessions(USER);
+        assert_eq!(sessions.len(), 2);
+        let untagged: Vec<&Session> = sessions.iter().filter(|s| s.tag.is_none()).collect();
+        assert_eq!(untagged.len(), 1);
+        assert!(untagged[0].end_boottime - untagged[0].start_boottime == 300);
+        assert!(find(&store, USER, SCOPE, USER, "root", None));
+    }
+
+    #[test]
+    fn expired_sessions_are_rejected() {
+        let store = MemorySessionStore::new();
+        create(&store, SCOPE, "root", None, 0);
+        assert!(!find(&store, USER, SCOPE, USER, "root", None));
+        assert!(store.sessions(USER).is_empty());
+
+        let now = get_boottime().unwrap();
+        let mut session = new_session(&store.key, USER, SCOPE.to_string(), USER, "root", None, 300).unwrap();
+        session.start_boottime = now - 600;
+        session.end_boottime = now - 300;
+        sign(&store.key, USER, &mut session);
+        add_session(&store, USER, session, false).unwrap();
+        assert!(!find(&store, USER, SCOPE, USER, "root", None));
+        assert!(store.sessions(USER).is_empty());
+    }
+
+    #[test]
+    fn sessions_from_another_boot_are_rejected() {
+        let store = MemorySessionStore::new();
+        let mut session = new_session(&store.key, USER, SCOPE.to_string(), USER, "root", None, 300).unwrap();
+        session.boot_id = String::from("00000000-0000-0000-0000-000000000000");
+        sign(&store.key, USER, &mut session);
+        add_session(&store, USER, session, false).unwrap();
+        assert!(!find(&store, USER, SCOPE, USER, "root", None));
+    }
+
+    #[test]
+    fn target_and_tag_must_match() {
+        let store = MemorySessionStore::new();
+        create(&store, SCOPE, "root", Some("web"), 300);
+
+        assert!(find(&store, USER, SCOPE, USER, "root", Some("web")));
+        assert!(!find(&store, USER, SCOPE, USER, "root", None));
+        assert!(!find(&store, USER, SCOPE, USER, "root", Some("db")));
+        assert!(!find(&store, USER, SCOPE, USER, "bob", Some("web")));
+        assert!(!find(&store, USER, SCOPE, "root", "root", Some("web")));
+    }
+
+    #[test]
+    fn tampered_sessions_are_rejected() {
+        let store = MemorySessionStore::new();
+        create(&store, SCOPE, "root", None, 300);
+        tamper(&store, &|s| s.end_boottime += 3600);
+        assert!(!find(&store, USER, SCOPE, USER, "root", None));
+        assert!(store.sessions(USER).is_empty());
+
+        create(&store, SCOPE, "bob", None, 300);
+        tamper(&store, &|s| s.target_user = String::from("root"));
+        assert!(!find(&store, USER, SCOPE, USER, "root", None));
+
+        create(&store, SCOPE, "root", None, 300);
+        tamper(&store, &|s| s.mac = s.mac.replace(|c: char| c != '0', "0"));
+        assert!(!find(&store, USER, SCOPE, USER, "root", None));
+    }
+
+    #[test]
+    fn sessions_are_bound_to_user_and_key() {
+        let store = MemorySessionStore::new();
+        create(&store, SCOPE, "root", None, 300);
+
+        // Copied to another user
+        let copied = store.sessions(USER);
+        store.update("bob", &mut |sessions| {
+            sessions.extend(copied.iter().cloned());
+            Ok(())
+        }).unwrap();
+        assert!(!find(&store, "bob", SCOPE, USER, "root", None));
+
+        // Signed with another key
+        let session = new_session(&[0xa5; 32], USER, SCOPE.to_string(), USER, "root", Some("web"), 300).unwrap();
+        add_session(&store, USER, session, false).unwrap();
+        assert!(!find(&store, USER, SCOPE, USER, "root", Some("web")));
+        assert!(find(&store, USER, SCOPE, USER, "root", None));
+    }
+
+    #[test]
+    fn revoke_matches_whole_ids() {
+        let store = MemorySessionStore::new();
+        create(&store, SCOPE, "root", None, 300);
+        create(&store, "global", "root", None, 300);
+        let id = store.sessions(USER)[0].mac[..SESSION_ID_LEN].to_string();
+
+        assert!(remove_sessions_by_id(&store, USER, Some("")).is_err());
+        assert!(remove_sessions_by_id(&store, USER, Some(&id[..1])).is_err());
+        assert!(remove_sessions_by_id(&store, USER, Some(&store.sessions(USER)[0].mac)).is_err());
+        assert_eq!(store.sessions(USER).len(), 2);
+
+        assert_eq!(remove_sessions_by_id(&store, USER, Some("zzzzzzzz")).unwrap(), 0);
+        assert_eq!(remove_sessions_by_id(&store, USER, Some(&id)).unwrap(), 1);
+        assert_eq!(store.sessions(USER).len(), 1);
+        assert_eq!(remove_sessions_by_id(&store, USER, None).unwrap(), 1);
+        assert!(store.sessions(USER).is_empty());
+    }
+}
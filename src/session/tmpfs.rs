@@ -0,0 +1,72 @@
+//!
+//! Session store that is guaranteed to only keep sessions in memory
+//!
+
+use std::io;
+use std::path::Path;
+use std::error::Error;
+use std::ffi::CString;
+use std::mem::MaybeUninit;
+use std::os::unix::ffi::OsStrExt;
+
+use zeroize::Zeroizing;
+use libc::{statfs, TMPFS_MAGIC};
+
+use session::SessionStore;
+use session::SessionUpdate;
+use session::file::FileSessionStore;
+
+use TMPFS_SESSION_PATH;
+use TMPFS_SESSION_KEY_PATH;
+
+/// Keeps sessions and their key in files like `FileSessionStore`, but refuses to work
+/// unless they're on a tmpfs mount, so nothing ever reaches the disk
+pub struct TmpfsSessionStore {
+    files: FileSessionStore,
+}
+
+/// Make sure the directory at path is on a tmpfs mount
+fn verify_tmpfs(path: &Path) -> Result<(), Box<dyn Error>> {
+    let path_c = CString::new(path.as_os_str().as_bytes())?;
+    let mut buf = MaybeUninit::<libc::statfs>::uninit();
+    if unsafe { statfs(path_c.as_ptr(), buf.as_mut_ptr()) } != 0 {
+        return Err(Box::new(io::Error::last_os_error()));
+    }
+
+    let buf = unsafe { buf.assume_init() };
+    if buf.f_type != TMPFS_MAGIC {
+        return Err(From::from(format!("{} isn't on a tmpfs mount!", path.display())));
+    }
+    Ok(())
+}
+
+impl TmpfsSessionStore {
+    /// Fails if the directories the sessions and the key would be kept in aren't on tmpfs
+    pub fn new() -> Result<TmpfsSessionStore, Box<dyn Error>> {
+        for path in [TMPFS_SESSION_PATH, TMPFS_SESSION_KEY_PATH].iter() {
+            let parent = Path::new(path).parent().ok_or("Session paths must not be the root directory!")?;
+            verify_tmpfs(parent)?;
+        }
+        Ok(TmpfsSessionStore {
+            files: FileSessionStore::new(TMPFS_SESSION_PATH, TMPFS_SESSION_KEY_PATH),
+        })
+    }
+}
+
+impl SessionStore for TmpfsSessionStore {
+    fn get_key(&self) -> Result<Zeroizing<Vec<u8>>, Box<dyn Error>> {
+        self.files.get_key()
+    }
+
+    fn update(&self, username: &str, f: &mut SessionUpdate) -> Result<(), Box<dyn Error>> {
+        // Something could have been mounted over the session directory in the meantime
+        if Path::new(TMPFS_SESSION_PATH).exists() {
+            verify_tmpfs(Path::new(TMPFS_SESSION_PATH))?;
+        }
+        self.files.update(username, f)
+    }
+
+    fn list_users(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        self.files.list_users()
+    }
+}
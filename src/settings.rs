@@ -2,45 +2,413 @@ use std::io::prelude::*;
 use std::fs::File;
 use std::error::Error;
 use std::path::Path;
+use std::ptr;
+use std::ffi::CString;
 
 use serde_json;
 use which::which;
+use libc::{fnmatch, FNM_PATHNAME, FNM_PERIOD};
 
 use DEFAULT_PROMPT;
 use DEFAULT_SESSION_TIMEOUT;
+use DEFAULT_PASSWORD_TIMEOUT;
+use DEFAULT_LOCKOUT_MAX_FAILURES;
+use DEFAULT_LOCKOUT_SEC;
+use DEFAULT_MAX_LOCKOUT_SEC;
+use DEFAULT_AUDIT_LOG_PATH;
+use DEFAULT_HOOK_TIMEOUT;
+
+/// Special values of `auth_user`. Any other value names a specific account.
+pub static AUTH_USER_INVOKER: &str = "invoker";
+pub static AUTH_USER_TARGET: &str = "target";
+pub static AUTH_USER_ROOT: &str = "root";
+
+/// Names of all authentication frameworks that may appear in an auth chain
+pub static AUTH_FRAMEWORK_NAMES: [&str; 3] = ["pam", "touchid", "totp"];
 
 // List of permissions
 #[derive(Serialize, Deserialize)]
 pub struct Permissions {
     pub allowed_commands: Vec<String>,
+    #[serde(default)]
+    pub allow_shell: bool, // May run shells with -i and -s. Not implied by "*".
+    #[serde(default)]
+    pub allowed_edits: Vec<String>, // Files that may be edited with -e, as absolute paths or globs
+}
+
+/// A group of commands with its own overrides of the global settings.
+/// Rules are checked in order before the user's `permissions`.
+#[derive(Serialize, Deserialize)]
+pub struct Rule {
+    pub allowed_commands: Vec<String>,
+    #[serde(default)]
+    pub auth_chain: Option<Vec<AuthEntry>>,
+    #[serde(default)]
+    pub auth_user: Option<String>,
+    #[serde(default)]
+    pub tag: Option<String>, // Sessions only cover commands of rules with the same tag
+    #[serde(default)]
+    pub allow_shell: bool,   // Shells started with -i and -s use this rule
+    #[serde(default)]
+    pub log_io: bool,        // Record the I/O of commands run with this rule under IO_LOG_PATH
+    #[serde(default)]
+    pub allowed_edits: Vec<String>, // Files that may be edited with -e using this rule
+    #[serde(default)]
+    pub timeout_sec: Option<u64>, // Stop commands run with this rule once they run for longer
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct User {
     pub username: String,
-    pub permissions: Permissions
+    pub permissions: Permissions,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<Rule>,
+}
+
+/// How the result of a framework in an auth chain is treated, as in PAM
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ControlFlag {
+    Required,   // Must succeed. The rest of the chain still runs after a failure.
+    Requisite,  // Must succeed. A failure ends authentication.
+    Sufficient, // Success ends authentication if nothing required has failed
+    Optional,   // Only matters if nothing else in the chain succeeds
+}
+
+/// What a session is bound to. Another invocation only reuses a session with the same scope.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionScope {
+    #[default]
+    Tty,    // The TTY, as long as it isn't recreated for another login
+    Ppid,   // The parent process, usually the shell rudo was run from
+    Login,  // The kernel audit session, shared by everything in one login
+    Global, // Any invocation by the same user
+}
+
+/// Where sessions are kept
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionStoreType {
+    #[default]
+    File,  // Files under SESSION_PATH
+    Tmpfs, // Files under TMPFS_SESSION_PATH, which has to be on tmpfs
+}
+
+/// Where audit events are sent
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditSinkType {
+    File,     // One JSON line per event in audit_log
+    Syslog,   // RFC 5424 messages to /dev/log
+    Journald, // journald's native socket, with the event's fields as RUDO_* fields
+}
+
+/// Syslog facilities that audit events can be sent to
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SyslogFacility {
+    User,
+    Daemon,
+    Auth,
+    #[default]
+    Authpriv,
+    Local0,
+    Local1,
+    Local2,
+    Local3,
+    Local4,
+    Local5,
+    Local6,
+    Local7,
+}
+
+impl SyslogFacility {
+    /// Numerical code of the facility, as used in syslog messages
+    pub fn code(self) -> i32 {
+        match self {
+            SyslogFacility::User => 1,
+            SyslogFacility::Daemon => 3,
+            SyslogFacility::Auth => 4,
+            SyslogFacility::Authpriv => 10,
+            SyslogFacility::Local0 => 16,
+            SyslogFacility::Local1 => 17,
+            SyslogFacility::Local2 => 18,
+            SyslogFacility::Local3 => 19,
+            SyslogFacility::Local4 => 20,
+            SyslogFacility::Local5 => 21,
+            SyslogFacility::Local6 => 22,
+            SyslogFacility::Local7 => 23,
+        }
+    }
+}
+
+/// Syslog priorities, from most to least severe
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum SyslogPriority {
+    Emerg,
+    Alert,
+    Crit,
+    Err,
+    Warning,
+    Notice,
+    Info,
+    Debug,
+}
+
+impl SyslogPriority {
+    /// Numerical code of the priority, as used in syslog messages
+    pub fn code(self) -> i32 {
+        self as i32
+    }
+}
+
+/// How audit events are sent to syslog and journald
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct SyslogSettings {
+    pub facility: SyslogFacility,
+    pub priority: SyslogPriority,      // Priority of allowed commands
+    pub deny_priority: SyslogPriority, // Priority of denied commands and failed authentication
+}
+
+impl Default for SyslogSettings {
+    fn default() -> SyslogSettings {
+        SyslogSettings {
+            facility: SyslogFacility::default(),
+            priority: SyslogPriority::Notice,
+            deny_priority: SyslogPriority::Alert,
+        }
+    }
+}
+
+/// Events that hooks can be run for
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum HookEvent {
+    AuthFailure, // A user failed to authenticate
+    Denied,      // A user authenticated, but the command isn't allowed
+    Lockout,     // A user was locked out after too many failed attempts
+    Executed,    // A command or shell is about to run
+}
+
+/// A program that's notified of events
+#[derive(Serialize, Deserialize)]
+pub struct Hook {
+    pub command: String,      // Absolute path of the program
+    #[serde(default)]
+    pub args: Vec<String>,
+    pub events: Vec<HookEvent>,
+    pub user: String,         // Unprivileged account the program runs as
+    #[serde(default = "default_hook_timeout")]
+    pub timeout_sec: u64,     // How long the program may run before it's killed
+    #[serde(default)]
+    pub tags: Vec<String>,    // Only run for commands of rules with one of these tags, if any are given
+}
 
+/// A single framework in an auth chain
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AuthEntry {
+    pub framework: String,
+    pub control: ControlFlag,
+}
+
+/// Thresholds for locking out users after repeated failed attempts
+#[derive(Serialize, Deserialize)]
+pub struct LockoutSettings {
+    pub max_failures: u32,     // Failed attempts before a lockout, or 0 to disable lockouts
+    pub lockout_sec: i64,      // Length of the first lockout, doubled for each consecutive one
+    pub max_lockout_sec: i64,  // Upper bound on the length of a lockout
+}
+
+impl Default for LockoutSettings {
+    fn default() -> LockoutSettings {
+        LockoutSettings {
+            max_failures: DEFAULT_LOCKOUT_MAX_FAILURES,
+            lockout_sec: DEFAULT_LOCKOUT_SEC,
+            max_lockout_sec: DEFAULT_MAX_LOCKOUT_SEC,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct Settings {
     pub prompt: String,
     pub session_timeout_sec: i64,
+    #[serde(default)]
+    pub session_scope: SessionScope,
+    #[serde(default)]
+    pub session_store: SessionStoreType,
+    #[serde(default = "default_auth_chain")]
+    pub auth_chain: Vec<AuthEntry>,
+    #[serde(default = "default_auth_user")]
+    pub auth_user: String,
+    #[serde(default)]
+    pub lockout: LockoutSettings,
+    #[serde(default = "default_password_timeout")]
+    pub password_timeout_sec: u64, // How long a prompt waits for input, or 0 to wait forever
+    #[serde(default)]
+    pub pwfeedback: bool,          // Print a `*` for every character typed at a prompt
+    #[serde(default)]
+    pub use_pty: bool,             // Run commands under a new pty instead of the user's terminal
+    #[serde(default)]
+    pub monitor: bool,             // Keep rudo running as the parent of commands instead of replacing it
+    #[serde(default)]
+    pub askpass: Option<String>, // Askpass helper for -A, overrides $RUDO_ASKPASS
+    #[serde(default = "default_audit_log")]
+    pub audit_log: String,       // File that the `file` audit sink writes to
+    #[serde(default = "default_audit_sinks")]
+    pub audit_sinks: Vec<AuditSinkType>,
+    #[serde(default)]
+    pub syslog: SyslogSettings,  // Facility and priorities of the `syslog` and `journald` sinks
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hooks: Vec<Hook>,
     pub allowed_users: Vec<User>,
 }
 
+/// Result of matching a command against a user's permissions
+pub struct CommandMatch<'a> {
+    pub path: String,           // Canonical path of the command to run
+    pub rule: Option<&'a Rule>, // Rule that allowed the command, if not the user's `permissions`
+}
+
+/// Default auth chain: TouchID if available, falling back to a password
+fn default_auth_chain() -> Vec<AuthEntry> {
+    let mut chain = Vec::new();
+    if cfg!(feature = "touchid") {
+        chain.push(AuthEntry { framework: String::from("touchid"), control: ControlFlag::Sufficient });
+    }
+    chain.push(AuthEntry { framework: String::from("pam"), control: ControlFlag::Required });
+    chain
+}
+
+fn default_password_timeout() -> u64 {
+    DEFAULT_PASSWORD_TIMEOUT
+}
+
+fn default_audit_log() -> String {
+    String::from(DEFAULT_AUDIT_LOG_PATH)
+}
+
+fn default_hook_timeout() -> u64 {
+    DEFAULT_HOOK_TIMEOUT
+}
+
+fn default_audit_sinks() -> Vec<AuditSinkType> {
+    vec![AuditSinkType::File]
+}
+
+/// By default users authenticate with their own credentials
+fn default_auth_user() -> String {
+    String::from(AUTH_USER_INVOKER)
+}
+
+fn validate_auth_chain(chain: &[AuthEntry]) -> Option<Box<dyn Error>> {
+    if chain.is_empty() {
+        return Some(From::from("auth_chain must contain at least one framework"));
+    }
+    for entry in chain {
+        if !AUTH_FRAMEWORK_NAMES.contains(&entry.framework.as_str()) {
+            return Some(From::from(format!("Unknown authentication framework in auth_chain: {}",
+                                           entry.framework)));
+        }
+    }
+    None
+}
+
+fn validate_commands(commands: &[String]) -> Option<Box<dyn Error>> {
+    // Check that all paths in allowed commands are absolute
+    for cmd in commands {
+        if cmd == "*" { continue; }
+
+        if !cmd.starts_with('/') {
+            return Some(From::from("Only absolute paths are allowed in allowed_commands"));
+        }
+    }
+    None
+}
+
+fn validate_edits(files: &[String]) -> Option<Box<dyn Error>> {
+    if files.iter().any(|f| !f.starts_with('/')) {
+        return Some(From::from("Only absolute paths are allowed in allowed_edits"));
+    }
+    None
+}
+
+/// Whether one of the paths or globs in `allowed` matches path. Wildcards don't match `/`
+/// or a leading `.`, so they never reach into other directories or hidden files.
+fn match_edit(allowed: &[String], path: &str) -> bool {
+    let c_path = match CString::new(path) {
+        Ok(p) => p,
+        Err(_) => return false,
+    };
+    allowed.iter().any(|pattern| CString::new(pattern.as_str()).is_ok_and(|p| {
+        unsafe { fnmatch(p.as_ptr(), c_path.as_ptr(), FNM_PATHNAME | FNM_PERIOD) == 0 }
+    }))
+}
+
+/// Return the canonical path of `command` if `allowed` permits it
+fn match_command(allowed: &[String], command: &str) -> Result<Option<String>, Box<dyn Error>> {
+    for perm in allowed {
+        if perm == "*" {
+            return Ok(Some(command.to_string()));
+        }
+
+        let perm_path = Path::new(perm);
+        let perm_canonical = match perm_path.canonicalize() {
+            Ok(v) => v,
+            Err(_) => { continue; }
+        };
+
+        if command.contains("/") {
+            // If a path was given, canonicalize and check for direct match
+            let command_path = Path::new(command);
+            let command_canonical = command_path.canonicalize()?;
+
+            if perm_canonical == command_canonical {
+                return Ok(Some(command_canonical.into_os_string().into_string().unwrap()));
+            }
+        } else {
+            // If a non-path command name was given, resolve it in PATH and compare
+            // the result against the permission's canonical path.
+            let command_pathbuf = which(command)?;
+            let command_canonical = command_pathbuf.as_path().canonicalize()?;
+
+            if perm_canonical == command_canonical {
+                return Ok(Some(command_canonical.into_os_string().into_string().unwrap()));
+            }
+        }
+    }
+    Ok(None)
+}
+
 impl Settings {
     pub fn new() -> Settings {
         // Create an empty Settings struct with `root` as the only user
         let mut s = Settings {
             prompt: String::from(DEFAULT_PROMPT),
             session_timeout_sec: DEFAULT_SESSION_TIMEOUT,
+            session_scope: SessionScope::default(),
+            session_store: SessionStoreType::default(),
+            auth_chain: default_auth_chain(),
+            auth_user: default_auth_user(),
+            lockout: LockoutSettings::default(),
+            password_timeout_sec: DEFAULT_PASSWORD_TIMEOUT,
+            pwfeedback: false,
+            use_pty: false,
+            monitor: false,
+            askpass: None,
+            audit_log: default_audit_log(),
+            audit_sinks: default_audit_sinks(),
+            syslog: SyslogSettings::default(),
+            hooks: Vec::new(),
             allowed_users: Vec::new(),
         };
 
         let mut root = User {
             username: String::from("root"),
-            permissions: Permissions{ allowed_commands: Vec::new() },
+            permissions: Permissions{ allowed_commands: Vec::new(), allow_shell: false, allowed_edits: Vec::new() },
+            rules: Vec::new(),
         };
         root.permissions.allowed_commands.push(String::from("*"));
         s.allowed_users.push(root);
@@ -49,13 +417,71 @@ impl Settings {
     }
 
     fn validate(&self) -> Option<Box<dyn Error>> {
-        // Check that all paths in allowed commands are absolute
+        if let Some(e) = validate_auth_chain(&self.auth_chain) {
+            return Some(e);
+        }
+
+        if self.auth_user.is_empty() || self.allowed_users.iter()
+            .any(|u| u.rules.iter().any(|r| r.auth_user.as_ref().is_some_and(|a| a.is_empty()))) {
+            return Some(From::from("auth_user can't be empty"));
+        }
+        if self.allowed_users.iter()
+            .any(|u| u.rules.iter().any(|r| r.tag.as_ref().is_some_and(|t| t.is_empty()))) {
+            return Some(From::from("Rule tags can't be empty"));
+        }
+
+        if cfg!(not(target_os = "linux")) && self.session_store == SessionStoreType::Tmpfs {
+            return Some(From::from("session_store \"tmpfs\" is only supported on Linux"));
+        }
+
+        if !self.audit_log.starts_with('/') {
+            return Some(From::from("audit_log must be an absolute path"));
+        }
+        if self.audit_sinks.is_empty() {
+            return Some(From::from("audit_sinks must contain at least one sink"));
+        }
+        if cfg!(not(target_os = "linux")) && self.audit_sinks.contains(&AuditSinkType::Journald) {
+            return Some(From::from("audit sink \"journald\" is only supported on Linux"));
+        }
+
+        for hook in &self.hooks {
+            if !hook.command.starts_with('/') {
+                return Some(From::from("Hook commands must be absolute paths"));
+            }
+            if hook.user.is_empty() || hook.user == "root" {
+                return Some(From::from("Hooks must run as an unprivileged user"));
+            }
+            if hook.timeout_sec == 0 {
+                return Some(From::from("Hook timeouts must be at least one second"));
+            }
+        }
+
+        if self.lockout.lockout_sec < 0 || self.lockout.max_lockout_sec < self.lockout.lockout_sec {
+            return Some(From::from("lockout intervals must satisfy 0 <= lockout_sec <= max_lockout_sec"));
+        }
+
         for user in &self.allowed_users {
-            for cmd in &user.permissions.allowed_commands {
-                if cmd == "*" { continue; }
+            if let Some(e) = validate_commands(&user.permissions.allowed_commands) {
+                return Some(e);
+            }
+            if let Some(e) = validate_edits(&user.permissions.allowed_edits) {
+                return Some(e);
+            }
 
-                if cmd.chars().nth(0).unwrap() != '/' {
-                    return Some(From::from("Only absolute paths are allowed in allowed_commands"));
+            for rule in &user.rules {
+                if let Some(e) = validate_commands(&rule.allowed_commands) {
+                    return Some(e);
+                }
+                if let Some(e) = validate_edits(&rule.allowed_edits) {
+                    return Some(e);
+                }
+                if let Some(ref chain) = rule.auth_chain {
+                    if let Some(e) = validate_auth_chain(chain) {
+                        return Some(e);
+                    }
+                }
+                if rule.timeout_sec == Some(0) {
+                    return Some(From::from("Rule timeouts must be at least one second"));
                 }
             }
         }
@@ -93,42 +519,87 @@ impl Settings {
         user
     }
 
-    pub fn sanitize_user_command(&self, username: &str, command: &str) -> Result<String, Box<dyn Error>> {
+    pub fn sanitize_user_command(&self, username: &str, command: &str) -> Result<CommandMatch<'_>, Box<dyn Error>> {
         // Find the user's config entry
         let user: &User = self.get_user(username)?;
 
-        // See if the user has permission to run this command
-        for perm in &user.permissions.allowed_commands {
-            if perm == "*" {
-                return Ok(command.to_string());
+        // Rules take precedence over the user's general permissions
+        for rule in &user.rules {
+            if let Some(path) = match_command(&rule.allowed_commands, command)? {
+                return Ok(CommandMatch { path, rule: Some(rule) });
             }
+        }
 
-            let perm_path = Path::new(perm);
-            let perm_canonical = match perm_path.canonicalize() {
-                Ok(v) => v,
-                Err(_) => { continue; }
-            };
+        // See if the user has permission to run this command
+        if let Some(path) = match_command(&user.permissions.allowed_commands, command)? {
+            return Ok(CommandMatch { path, rule: None });
+        }
 
-            if command.contains("/") {
-                // If a path was given, canonicalize and check for direct match
-                let command_path = Path::new(command);
-                let command_canonical = command_path.canonicalize()?;
+        Err(From::from("Command not present in `allowed_commands`"))
+    }
 
-                if perm_canonical == command_canonical {
-                    return Ok(command_canonical.into_os_string().into_string().unwrap());
-                }
-            } else {
-                // If a non-path command name was given, resolve it in PATH and compare
-                // the result against the permission's canonical path.
-                let command_pathbuf = which(command)?;
-                let command_canonical = command_pathbuf.as_path().canonicalize()?;
-
-                if perm_canonical == command_canonical {
-                    return Ok(command_canonical.into_os_string().into_string().unwrap());
-                }
-            }
+    /// Check that the user may run a shell with -i or -s. Returns the rule that allows it,
+    /// or None if it's allowed by the user's general permissions.
+    pub fn sanitize_user_shell(&self, username: &str) -> Result<Option<&Rule>, Box<dyn Error>> {
+        let user: &User = self.get_user(username)?;
+
+        if let Some(rule) = user.rules.iter().find(|r| r.allow_shell) {
+            return Ok(Some(rule));
+        }
+        if user.permissions.allow_shell {
+            return Ok(None);
+        }
+
+        Err(From::from("Shells are not allowed by `allow_shell`"))
+    }
+
+    /// Check that the user may edit all of the given files with -e. Returns the first rule
+    /// that allows all of them, or None if they're allowed by the user's general permissions.
+    pub fn sanitize_user_edit(&self, username: &str, paths: &[String]) -> Result<Option<&Rule>, Box<dyn Error>> {
+        let user: &User = self.get_user(username)?;
+
+        if let Some(rule) = user.rules.iter().find(|r| paths.iter().all(|p| match_edit(&r.allowed_edits, p))) {
+            return Ok(Some(rule));
+        }
+        if paths.iter().all(|p| match_edit(&user.permissions.allowed_edits, p)) {
+            return Ok(None);
+        }
+
+        Err(From::from("File not present in `allowed_edits`"))
+    }
+
+    /// Describe where a rule is in the user's config entry: `rules[<index>]`,
+    /// or `permissions` for the user's general permissions
+    pub fn describe_rule(&self, username: &str, rule: Option<&Rule>) -> String {
+        let index = rule.and_then(|rule| self.get_user(username).ok()
+                                  .and_then(|u| u.rules.iter().position(|r| ptr::eq(r, rule))));
+        match index {
+            Some(i) => format!("rules[{}]", i),
+            None => String::from("permissions"),
+        }
+    }
+
+    /// Get the auth chain that applies to the given rule
+    pub fn get_auth_chain<'a>(&'a self, rule: Option<&'a Rule>) -> &'a [AuthEntry] {
+        match rule.and_then(|r| r.auth_chain.as_ref()) {
+            Some(chain) => chain,
+            None => &self.auth_chain,
+        }
+    }
+
+    /// Get the account whose credentials the invoking user has to present for the
+    /// given rule, when running a command as `target`
+    pub fn get_auth_user(&self, rule: Option<&Rule>, invoker: &str, target: &str) -> String {
+        let mode = rule.and_then(|r| r.auth_user.as_ref()).unwrap_or(&self.auth_user);
+        if mode == AUTH_USER_INVOKER {
+            invoker.to_string()
+        } else if mode == AUTH_USER_TARGET {
+            target.to_string()
+        } else if mode == AUTH_USER_ROOT {
+            String::from("root")
+        } else {
+            mode.clone()
         }
-        Err(From::from("Command not present in `allowed_commands`"))
     }
 
     // Get the current prompt or return the default if none is present in config